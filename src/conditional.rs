@@ -6,7 +6,7 @@ use http::{Method, StatusCode};
 use crate::davheaders::{self, ETag};
 use crate::davpath::DavPath;
 use crate::fs::{DavFileSystem, DavMetaData};
-use crate::ls::DavLockSystem;
+use crate::ls_async::DynLockSystemAsync;
 
 type Request = http::Request<()>;
 
@@ -115,7 +115,7 @@ pub(crate) fn http_if_match(req: &Request, meta: Option<&Box<dyn DavMetaData>>)
 pub(crate) async fn dav_if_match<'a>(
     req: &'a Request,
     fs: &'a Box<dyn DavFileSystem + 'static>,
-    ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
+    ls: &'a Option<DynLockSystemAsync>,
     path: &'a DavPath,
 ) -> (bool, Vec<String>)
 {
@@ -168,7 +168,7 @@ pub(crate) async fn dav_if_match<'a>(
                         false
                     } else {
                         match ls {
-                            &Some(ref ls) => ls.check(p, None, true, false, vec![s]).is_ok(),
+                            &Some(ref ls) => ls.check(p, None, true, false, vec![s]).await.is_ok(),
                             &None => false,
                         }
                     }
@@ -216,7 +216,7 @@ pub(crate) async fn if_match<'a>(
     req: &'a Request,
     meta: Option<&'a Box<dyn DavMetaData + 'static>>,
     fs: &'a Box<dyn DavFileSystem + 'static>,
-    ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
+    ls: &'a Option<DynLockSystemAsync>,
     path: &'a DavPath,
 ) -> Option<StatusCode>
 {
@@ -232,7 +232,7 @@ pub(crate) async fn if_match_get_tokens<'a>(
     req: &'a Request,
     meta: Option<&'a Box<dyn DavMetaData + 'static>>,
     fs: &'a Box<dyn DavFileSystem + 'static>,
-    ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
+    ls: &'a Option<DynLockSystemAsync>,
     path: &'a DavPath,
 ) -> Result<Vec<String>, StatusCode>
 {
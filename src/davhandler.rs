@@ -20,6 +20,7 @@ use crate::util::{dav_method, DavMethod, DavMethodSet};
 use crate::errors::DavError;
 use crate::fs::*;
 use crate::ls::*;
+use crate::ls_async::DynLockSystemAsync;
 use crate::voidfs::{is_voidfs, VoidFs};
 use crate::DavResult;
 
@@ -41,7 +42,7 @@ pub struct DavConfig {
     // Filesystem backend.
     pub(crate) fs:            Option<Box<dyn DavFileSystem>>,
     // Locksystem backend.
-    pub(crate) ls:            Option<Box<dyn DavLockSystem>>,
+    pub(crate) ls:            Option<DynLockSystemAsync>,
     // Set of allowed methods (None means "all methods")
     pub(crate) allow:         Option<DavMethodSet>,
     // Principal is webdav speak for "user", used to give locks an owner (if a locksystem is
@@ -85,6 +86,18 @@ impl DavConfig {
 
     /// Set the locksystem to use.
     pub fn locksystem(self, ls: Box<dyn DavLockSystem>) -> Self {
+        self.locksystem_async(Arc::new(ls))
+    }
+
+    /// Set the locksystem to use, as a shared trait object.
+    pub fn locksystem_dyn(self, ls: DynLockSystem) -> Self {
+        self.locksystem_async(Arc::new(ls))
+    }
+
+    /// Set the locksystem to use, one that implements the async
+    /// `DavLockSystemAsync` trait. Use this for a locksystem that does
+    /// I/O, for example one that keeps its locks in a database.
+    pub fn locksystem_async(self, ls: DynLockSystemAsync) -> Self {
         let mut this = self;
         this.ls = Some(ls);
         this
@@ -146,7 +159,7 @@ impl DavConfig {
 pub(crate) struct DavInner {
     pub prefix:        String,
     pub fs:            Box<dyn DavFileSystem>,
-    pub ls:            Option<Box<dyn DavLockSystem>>,
+    pub ls:            Option<DynLockSystemAsync>,
     pub allow:         Option<DavMethodSet>,
     pub principal:     Option<String>,
     pub hide_symlinks: Option<bool>,
@@ -295,7 +308,10 @@ impl DavHandler {
 impl DavInner {
     // helper.
     pub(crate) async fn has_parent<'a>(&'a self, path: &'a DavPath) -> bool {
-        let p = path.parent();
+        let p = match path.parent() {
+            Some(p) => p,
+            None => return true,
+        };
         self.fs.metadata(&p).await.map(|m| m.is_dir()).unwrap_or(false)
     }
 
@@ -309,6 +309,11 @@ impl ETag {
     pub fn is_weak(&self) -> bool {
         self.weak
     }
+
+    // the entity-tag as it appears in a header, e.g. W/"1234".
+    pub fn as_str(&self) -> &str {
+        &self.tag
+    }
 }
 
 impl FromStr for ETag {
@@ -619,10 +624,13 @@ fn trim_left<'a>(mut out: &'a [u8]) -> &'a [u8] {
 fn scan_until(buf: &[u8], c: u8) -> Result<(&[u8], &[u8]), headers::Error> {
     let mut i = 1;
     let mut quote = false;
-    while quote || buf[i] != c {
-        if buf.is_empty() || is_whitespace(buf[i]) {
+    loop {
+        if i >= buf.len() || is_whitespace(buf[i]) {
             return Err(invalid());
         }
+        if !quote && buf[i] == c {
+            break;
+        }
         if buf[i] == b'"' {
             quote = !quote;
         }
@@ -745,8 +753,12 @@ impl Header for If {
                             if cur_list.conditions.is_empty() {
                                 IfState::Bad
                             } else {
+                                // a resource tag applies to all lists that follow it,
+                                // up until the next resource tag.
+                                let resource_tag = cur_list.resource_tag.clone();
                                 if_lists.0.push(cur_list);
                                 cur_list = IfList::new();
+                                cur_list.resource_tag = resource_tag;
                                 IfState::Start
                             }
                         },
@@ -785,6 +797,28 @@ mod tests {
         assert!(hdr.is_ok());
     }
 
+    #[test]
+    fn if_header_tagged_lists() {
+        let val = r#"<http://x.yz/a> (<urn:x:1>) (Not <urn:x:2>) <http://x.yz/b> (<urn:x:3>)"#;
+        let hdrval = HeaderValue::from_static(val);
+        let hdr = If::decode(&mut std::iter::once(&hdrval)).unwrap();
+        let tags: Vec<_> = hdr
+            .0
+            .iter()
+            .map(|l| l.resource_tag.as_ref().unwrap().path().to_string())
+            .collect();
+        assert_eq!(tags, vec!["/a", "/a", "/b"]);
+        assert!(hdr.0[1].conditions[0].not);
+    }
+
+    #[test]
+    fn if_header_unterminated() {
+        for val in &["(<urn:x:1", "([W/\"etag\"", "<http://x.yz/"] {
+            let hdrval = HeaderValue::from_static(val);
+            assert!(If::decode(&mut std::iter::once(&hdrval)).is_err());
+        }
+    }
+
     #[test]
     fn etag_header() {
         let t1 = ETag::from_str(r#"W/"12345""#).unwrap();
@@ -168,15 +168,7 @@ fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
 /// Comparision ignores any trailing slash, so /foo == /foo/
 impl PartialEq for DavPath {
     fn eq(&self, rhs: &DavPath) -> bool {
-        let mut a = self.fullpath.as_slice();
-        if a.len() > 1 && a.ends_with(b"/") {
-            a = &a[..a.len() - 1];
-        }
-        let mut b = rhs.fullpath.as_slice();
-        if b.len() > 1 && b.ends_with(b"/") {
-            b = &b[..b.len() - 1];
-        }
-        a == b
+        self.eq_ignore_trailing_slash(rhs)
     }
 }
 
@@ -241,13 +233,116 @@ impl DavPath {
     }
 
     /// from request.uri
+    ///
+    /// The path is percent-decoded segment by segment and normalized, like
+    /// in `DavPath::new`. It is rejected with `ParseError::InvalidPath` if:
+    ///
+    /// - it contains anything but printable ASCII, or a fragment (`#`)
+    /// - a segment decodes to a `/` (`%2F`), since that would change how
+    ///   the path is split into segments
+    /// - a segment decodes to a NUL byte (`%00`)
+    ///
+    /// Decoding is done exactly once, so `%252F` becomes a literal `%2F`
+    /// in the segment, and is accepted.
     pub fn from_uri(uri: &http::uri::Uri) -> Result<Self, ParseError> {
-        Ok(DavPath {
-            fullpath: uri.path().as_bytes().to_vec(),
-            pfxlen:   None,
+        match uri.path() {
+            "*" => {
+                Ok(DavPath {
+                    fullpath: b"*".to_vec(),
+                    pfxlen:   None,
+                })
+            },
+            path => DavPath::new(path),
+        }
+    }
+
+    /// Return a normalized copy of this path.
+    ///
+    /// `.` and `..` segments are resolved (`..` never goes above the root)
+    /// and consecutive slashes are merged. A trailing slash is kept.
+    /// The prefix is left alone.
+    pub fn normalize(&self) -> DavPath {
+        if self.is_star() {
+            return self.clone();
+        }
+        let path = self.get_path();
+        let mut segs: Vec<&[u8]> = Vec::new();
+        for seg in self.segments() {
+            match seg {
+                b"." => {},
+                b".." => {
+                    segs.pop();
+                },
+                s => segs.push(s),
+            }
+        }
+        let mut fullpath = self.get_prefix().to_vec();
+        for seg in &segs {
+            fullpath.push(b'/');
+            fullpath.extend_from_slice(seg);
+        }
+        if segs.is_empty() || path.ends_with(b"/") {
+            fullpath.push(b'/');
+        }
+        DavPath {
+            fullpath,
+            pfxlen: self.pfxlen,
+        }
+    }
+
+    /// Compare two paths (including the prefix), ignoring a trailing
+    /// slash, so `/a/b` and `/a/b/` are the same. This is also what
+    /// `==` does, but this method makes it explicit at the call site.
+    pub fn eq_ignore_trailing_slash(&self, other: &DavPath) -> bool {
+        fn strip(p: &[u8]) -> &[u8] {
+            if p.len() > 1 && p.ends_with(b"/") {
+                &p[..p.len() - 1]
+            } else {
+                p
+            }
+        }
+        strip(&self.fullpath) == strip(&other.fullpath)
+    }
+
+    /// Does this path start with `prefix`? The comparison is done on
+    /// whole segments, so `/abc` does not start with `/ab`, but it does
+    /// start with `/` and `/abc/`. Trailing slashes are ignored.
+    pub fn starts_with(&self, prefix: &DavPath) -> bool {
+        self.strip_prefix(prefix).is_some()
+    }
+
+    /// If this path starts with `prefix` (see `starts_with`), return
+    /// the rest of the path, as an absolute path. Stripping a path from
+    /// itself results in `/`. The trailing slash, if any, is kept.
+    pub fn strip_prefix(&self, prefix: &DavPath) -> Option<DavPath> {
+        let mut segs = self.segments();
+        for pseg in prefix.segments() {
+            if segs.next() != Some(pseg) {
+                return None;
+            }
+        }
+        let mut fullpath = Vec::new();
+        for seg in segs {
+            fullpath.push(b'/');
+            fullpath.extend_from_slice(seg);
+        }
+        if fullpath.is_empty() || self.is_collection() {
+            fullpath.push(b'/');
+        }
+        Some(DavPath {
+            fullpath,
+            pfxlen: None,
         })
     }
 
+    // ASCII-lowercased copy of this path. Non-ASCII bytes are left alone.
+    pub(crate) fn to_ascii_lowercase(&self) -> DavPath {
+        DavPath {
+            fullpath: self.fullpath.to_ascii_lowercase(),
+            pfxlen:   self.pfxlen,
+        }
+    }
+
     /// add a slash to the end of the path (if not already present).
     pub(crate) fn add_slash(&mut self) {
         if !self.is_collection() {
@@ -255,6 +350,18 @@ impl DavPath {
         }
     }
 
+    /// Make the path end in a slash if it is a collection, and not end
+    /// in a slash if it isn't. The root always stays `/`.
+    pub fn set_collection(&mut self, is_collection: bool) {
+        if is_collection {
+            self.add_slash();
+        } else {
+            while self.get_path().len() > 1 && self.is_collection() {
+                self.fullpath.pop();
+            }
+        }
+    }
+
     // add a slash
     pub(crate) fn add_slash_if(&mut self, b: bool) {
         if b && !self.is_collection() {
@@ -270,6 +377,24 @@ impl DavPath {
         self.fullpath.extend_from_slice(b);
     }
 
+    /// Return a new path with `segment` appended, separated by a single
+    /// slash. The new path is not a collection, so it has no trailing
+    /// slash. The prefix is kept.
+    ///
+    /// `segment` is raw, not URL encoded. It may not be empty, `.` or `..`,
+    /// and it may not contain a `/`.
+    pub fn join(&self, segment: &[u8]) -> Result<DavPath, ParseError> {
+        match segment {
+            b"" => return Err(ParseError::InvalidPath),
+            b"." | b".." => return Err(ParseError::ForbiddenPath),
+            s if s.contains(&b'/') => return Err(ParseError::InvalidPath),
+            _ => {},
+        }
+        let mut path = self.clone();
+        path.push_segment(segment);
+        Ok(path)
+    }
+
     // as URL encoded string, with prefix.
     pub(crate) fn as_url_string_with_prefix_debug(&self) -> String {
         let mut p = encode_path(self.get_path());
@@ -293,22 +418,23 @@ impl DavPath {
         std::str::from_utf8(self.get_prefix()).unwrap()
     }
 
-    /// Return the parent directory.
-    pub(crate) fn parent(&self) -> DavPath {
-        let mut segs = self
-            .fullpath
-            .split(|&c| c == b'/')
-            .filter(|e| e.len() > 0)
-            .collect::<Vec<&[u8]>>();
-        segs.pop();
-        if segs.len() > 0 {
-            segs.push(b"");
-        }
-        segs.insert(0, b"");
-        DavPath {
-            pfxlen:   self.pfxlen,
-            fullpath: segs.join(&b'/').to_vec(),
+    /// Return the parent collection, or `None` if this is the root.
+    ///
+    /// The parent always ends in a slash, so both `/a/b` and `/a/b/`
+    /// have parent `/a/`. The prefix is kept.
+    pub fn parent(&self) -> Option<DavPath> {
+        let mut segs = self.segments().collect::<Vec<&[u8]>>();
+        segs.pop()?;
+        let mut fullpath = self.get_prefix().to_vec();
+        for seg in &segs {
+            fullpath.push(b'/');
+            fullpath.extend_from_slice(seg);
         }
+        fullpath.push(b'/');
+        Some(DavPath {
+            pfxlen: self.pfxlen,
+            fullpath,
+        })
     }
 }
 
@@ -343,17 +469,41 @@ impl DavPathRef {
         PathBuf::from(os_string)
     }
 
-    /// as URL encoded string, with prefix.
+    /// as URL encoded string. Everything except the RFC3986 "unreserved"
+    /// characters and `/` is percent-encoded, so the result can be used
+    /// as a `href`. For a `DavPath` the prefix is not included, use
+    /// `with_prefix().as_url_string()` for that.
     pub fn as_url_string(&self) -> String {
         let p = encode_path(self.get_path());
         std::string::String::from_utf8(p).unwrap()
     }
 
     /// is this a collection i.e. does the original URL path end in "/".
+    /// The root `/` always is.
+    ///
+    /// This is only a syntactic hint, the filesystem is not consulted.
+    /// A client can leave out the slash when it refers to a directory, so
+    /// handlers that care call `set_collection` once they know better.
     pub fn is_collection(&self) -> bool {
         self.get_path().ends_with(b"/")
     }
 
+    /// Iterate over the segments of the path, not including the prefix.
+    ///
+    /// Empty segments are skipped, so `/a//b/` yields `a` and `b`, and
+    /// the root yields nothing. `.` and `..` are returned as-is, use
+    /// `normalize` first if the path might contain them.
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> {
+        self.get_path().split(|&c| c == b'/').filter(|s| !s.is_empty())
+    }
+
+    /// Like `segments`, but the first item is an empty segment that
+    /// stands for the root. Useful when walking a tree that has a node
+    /// for `/` itself.
+    pub fn segments_with_root(&self) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(&b""[..]).chain(self.segments())
+    }
+
     // non-public functions
     //
 
@@ -398,22 +548,15 @@ impl DavPathRef {
         DavPathRef::new(&path[..end])
     }
 
-    /// The filename is the last segment of the path. Can be empty.
-    pub(crate) fn file_name(&self) -> &[u8] {
-        let segs = self
-            .get_path()
-            .split(|&c| c == b'/')
-            .filter(|e| e.len() > 0)
-            .collect::<Vec<&[u8]>>();
-        if segs.len() > 0 {
-            segs[segs.len() - 1]
-        } else {
-            b""
-        }
+    /// The filename is the last segment of the path, or `None` if this is the root.
+    ///
+    /// A trailing slash is ignored, so the filename of `/a/b/` is `b`.
+    pub fn file_name(&self) -> Option<&[u8]> {
+        self.segments().last()
     }
 
     pub(crate) fn get_mime_type_str(&self) -> &'static str {
-        let name = self.file_name();
+        let name = self.file_name().unwrap_or(b"");
         let d = name.rsplitn(2, |&c| c == b'.').collect::<Vec<&[u8]>>();
         if d.len() > 1 {
             if let Ok(ext) = std::str::from_utf8(d[0]) {
@@ -425,3 +568,155 @@ impl DavPathRef {
         "application/octet-stream"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(s: &str) -> DavPath {
+        DavPath {
+            fullpath: s.as_bytes().to_vec(),
+            pfxlen:   None,
+        }
+    }
+
+    fn uri(s: &str) -> Result<DavPath, ParseError> {
+        DavPath::from_uri(&s.parse().unwrap())
+    }
+
+    #[test]
+    fn from_uri() {
+        assert_eq!(uri("/a%20b/c").unwrap().as_bytes(), b"/a b/c");
+        assert_eq!(uri("/a//./b/../c/").unwrap().as_bytes(), b"/a/c/");
+        assert!(uri("*").unwrap().is_star());
+        assert!(matches!(uri("/a%2Fb"), Err(ParseError::InvalidPath)));
+        assert!(matches!(uri("/a%2fb"), Err(ParseError::InvalidPath)));
+        assert!(matches!(uri("/a%00b"), Err(ParseError::InvalidPath)));
+        assert!(matches!(uri("/../a"), Err(ParseError::ForbiddenPath)));
+        // double-encoded: decoded once, so the segment is "a%2Fb".
+        let p = uri("/a%252Fb").unwrap();
+        assert_eq!(p.as_bytes(), b"/a%2Fb");
+        assert_eq!(p.file_name(), Some(&b"a%2Fb"[..]));
+    }
+
+    #[test]
+    fn as_url_string() {
+        let p = DavPath::new("/a%20b/c%23d/").unwrap();
+        assert_eq!(p.as_bytes(), b"/a b/c#d/");
+        assert_eq!(p.as_url_string(), "/a%20b/c%23d/");
+        let p = DavPath::new("/%C3%A9t%C3%A9%3F").unwrap();
+        assert_eq!(p.as_url_string(), "/%C3%A9t%C3%A9%3F");
+        let p = DavPath::from_str_and_prefix("/pfx/a%20b", "/pfx").unwrap();
+        assert_eq!(p.as_url_string(), "/a%20b");
+        assert_eq!(p.with_prefix().as_url_string(), "/pfx/a%20b");
+    }
+
+    #[test]
+    fn set_collection() {
+        let mut p = DavPath::new("/a/b").unwrap();
+        p.set_collection(true);
+        assert_eq!(p.as_bytes(), b"/a/b/");
+        p.set_collection(false);
+        assert_eq!(p.as_bytes(), b"/a/b");
+        let mut p = DavPath::new("/").unwrap();
+        p.set_collection(false);
+        assert_eq!(p.as_bytes(), b"/");
+        let mut p = DavPath::from_str_and_prefix("/pfx/", "/pfx").unwrap();
+        p.set_collection(false);
+        assert_eq!(p.with_prefix().as_bytes(), b"/pfx/");
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(raw("/a/../b").normalize().as_bytes(), b"/b");
+        assert_eq!(raw("/a/./b").normalize().as_bytes(), b"/a/b");
+        assert_eq!(raw("//a//b").normalize().as_bytes(), b"/a/b");
+        assert_eq!(raw("/a/b/").normalize().as_bytes(), b"/a/b/");
+        assert_eq!(raw("/../..").normalize().as_bytes(), b"/");
+        assert_eq!(raw("/").normalize().as_bytes(), b"/");
+    }
+
+    #[test]
+    fn parent_and_file_name() {
+        let p = DavPath::new("/a/b/").unwrap();
+        assert_eq!(p.parent().unwrap().as_bytes(), b"/a/");
+        assert_eq!(p.file_name(), Some(&b"b"[..]));
+        let p = DavPath::new("/a/b").unwrap();
+        assert_eq!(p.parent().unwrap().as_bytes(), b"/a/");
+        assert_eq!(p.file_name(), Some(&b"b"[..]));
+        let p = DavPath::new("/a").unwrap();
+        assert_eq!(p.parent().unwrap().as_bytes(), b"/");
+        let p = DavPath::new("/").unwrap();
+        assert!(p.parent().is_none());
+        assert!(p.file_name().is_none());
+    }
+
+    #[test]
+    fn starts_with() {
+        let p = DavPath::new("/abc/def/").unwrap();
+        assert!(p.starts_with(&DavPath::new("/").unwrap()));
+        assert!(p.starts_with(&DavPath::new("/abc").unwrap()));
+        assert!(p.starts_with(&DavPath::new("/abc/").unwrap()));
+        assert!(!p.starts_with(&DavPath::new("/ab").unwrap()));
+        let rest = p.strip_prefix(&DavPath::new("/abc").unwrap()).unwrap();
+        assert_eq!(rest.as_bytes(), b"/def/");
+        let rest = p.strip_prefix(&DavPath::new("/abc/def").unwrap()).unwrap();
+        assert_eq!(rest.as_bytes(), b"/");
+        assert!(p.strip_prefix(&DavPath::new("/abc/de").unwrap()).is_none());
+    }
+
+    #[test]
+    fn segments() {
+        let p = DavPath::from_str_and_prefix("/pfx/a//b/", "/pfx").unwrap();
+        assert_eq!(p.segments().collect::<Vec<_>>(), vec![&b"a"[..], &b"b"[..]]);
+        assert_eq!(
+            p.segments_with_root().collect::<Vec<_>>(),
+            vec![&b""[..], &b"a"[..], &b"b"[..]]
+        );
+        let p = DavPath::new("/").unwrap();
+        assert_eq!(p.segments().count(), 0);
+        assert_eq!(p.segments_with_root().collect::<Vec<_>>(), vec![&b""[..]]);
+    }
+
+    #[test]
+    fn join() {
+        let p = DavPath::new("/").unwrap();
+        assert_eq!(p.join(b"a").unwrap().as_bytes(), b"/a");
+        let p = DavPath::new("/a/").unwrap();
+        assert_eq!(p.join(b"b c").unwrap().as_bytes(), b"/a/b c");
+        let p = DavPath::new("/a").unwrap();
+        assert_eq!(p.join(b"b").unwrap().as_bytes(), b"/a/b");
+        let p = DavPath::from_str_and_prefix("/pfx/a/", "/pfx").unwrap();
+        assert_eq!(p.join(b"b").unwrap().with_prefix().as_bytes(), b"/pfx/a/b");
+        assert!(p.join(b"").is_err());
+        assert!(p.join(b"b/c").is_err());
+        assert!(p.join(b"/").is_err());
+        assert!(p.join(b"..").is_err());
+    }
+
+    #[test]
+    fn is_collection() {
+        let p = |s| DavPath::new(s).unwrap();
+        assert!(p("/a/").is_collection());
+        assert!(!p("/a").is_collection());
+        assert!(p("/").is_collection());
+        assert!(p("/a/b/").is_collection());
+        let p = DavPath::from_str_and_prefix("/pfx", "/pfx").unwrap();
+        assert!(p.is_collection());
+        let mut p = DavPath::new("/a").unwrap();
+        p.set_collection(true);
+        assert!(p.is_collection());
+    }
+
+    #[test]
+    fn eq_ignore_trailing_slash() {
+        let p = |s| DavPath::new(s).unwrap();
+        assert!(p("/a/b").eq_ignore_trailing_slash(&p("/a/b/")));
+        assert!(p("/a/b/").eq_ignore_trailing_slash(&p("/a/b")));
+        assert!(p("/a/b/").eq_ignore_trailing_slash(&p("/a/b/")));
+        assert!(p("/").eq_ignore_trailing_slash(&p("/")));
+        assert!(!p("/a/b").eq_ignore_trailing_slash(&p("/a/bc")));
+        assert!(!p("/a/b/").eq_ignore_trailing_slash(&p("/a")));
+        assert_eq!(p("/a/b"), p("/a/b/"));
+    }
+}
@@ -5,6 +5,7 @@ use http::StatusCode;
 use xml;
 
 use crate::fs::FsError;
+use crate::ls::LockError;
 
 pub(crate) type DavResult<T> = Result<T, DavError>;
 
@@ -75,6 +76,12 @@ impl From<FsError> for io::Error {
     }
 }
 
+impl From<LockError> for DavError {
+    fn from(e: LockError) -> Self {
+        DavError::Status(lockerror_to_status(&e))
+    }
+}
+
 impl From<io::Error> for DavError {
     fn from(e: io::Error) -> Self {
         DavError::IoError(e)
@@ -157,6 +164,21 @@ fn fserror_to_status(e: &FsError) -> StatusCode {
     }
 }
 
+fn lockerror_to_status(e: &LockError) -> StatusCode {
+    match e {
+        LockError::Conflict(_) => StatusCode::LOCKED,
+        LockError::TooManyLocks => StatusCode::LOCKED,
+        LockError::StorageFull => StatusCode::INSUFFICIENT_STORAGE,
+        LockError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        LockError::PathTooDeep => StatusCode::URI_TOO_LONG,
+        LockError::InvalidToken => StatusCode::INTERNAL_SERVER_ERROR,
+        LockError::InfiniteTimeout => StatusCode::BAD_REQUEST,
+        LockError::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+        LockError::OwnerTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        LockError::InvalidPath => StatusCode::BAD_REQUEST,
+    }
+}
+
 impl DavError {
     pub(crate) fn statuscode(&self) -> StatusCode {
         match self {
@@ -11,8 +11,10 @@
 //! LOCK/UNLOCK always succeed, checking for locktokens in
 //! If: headers always succeeds, and nothing is every really locked.
 //!
-//! `FakeLs` implements such a fake locksystem.
-use std::time::{Duration, SystemTime};
+//! `FakeLs` implements such a fake locksystem. `lock`, `unlock` and `refresh`
+//! always succeed, `check` always passes, and `discover` never
+//! returns any locks. It can also be used for read-only servers, or in tests.
+use std::time::Duration;
 
 use uuid::Uuid;
 use xmltree::Element;
@@ -51,54 +53,51 @@ impl DavLockSystem for FakeLs {
         principal: Option<&str>,
         owner: Option<&Element>,
         timeout: Option<Duration>,
-        shared: bool,
-        deep: bool,
-    ) -> Result<DavLock, DavLock>
+        scope: LockScope,
+        depth: LockDepth,
+        _submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>
     {
-        let timeout = tm_limit(timeout);
-        let timeout_at = SystemTime::now() + timeout;
-
-        let d = if deep { 'I' } else { '0' };
-        let s = if shared { 'S' } else { 'E' };
+        let d = if depth.is_infinity() { 'I' } else { '0' };
+        let s = if scope.is_shared() { 'S' } else { 'E' };
         let token = format!("opaquetoken:{}/{}/{}", Uuid::new_v4().hyphenated(), d, s);
 
-        let lock = DavLock {
-            token:      token,
-            path:       path.clone(),
-            principal:  principal.map(|s| s.to_string()),
-            owner:      owner.cloned(),
-            timeout_at: Some(timeout_at),
-            timeout:    Some(timeout),
-            shared:     shared,
-            deep:       deep,
-        };
+        let lock = DavLock::builder(path)
+            .token(token)
+            .principal(principal)
+            .owner(owner)
+            .timeout(Some(tm_limit(timeout)))
+            .scope(scope)
+            .depth(depth)
+            .build();
         debug!("lock {} created", &lock.token);
         Ok(lock)
     }
 
-    fn unlock(&self, _path: &DavPath, _token: &str) -> Result<(), ()> {
+    fn unlock(&self, _path: &DavPath, _principal: Option<&str>, _token: &str) -> Result<(), UnlockError> {
         Ok(())
     }
 
-    fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()> {
+    fn refresh(
+        &self,
+        path: &DavPath,
+        _principal: Option<&str>,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, RefreshError>
+    {
         debug!("refresh lock {}", token);
         let v: Vec<&str> = token.split('/').collect();
         let deep = v.len() > 1 && v[1] == "I";
         let shared = v.len() > 2 && v[2] == "S";
 
-        let timeout = tm_limit(timeout);
-        let timeout_at = SystemTime::now() + timeout;
-
-        let lock = DavLock {
-            token:      token.to_string(),
-            path:       path.clone(),
-            principal:  None,
-            owner:      None,
-            timeout_at: Some(timeout_at),
-            timeout:    Some(timeout),
-            shared:     shared,
-            deep:       deep,
-        };
+        // we do not keep state, so we don't know when it was created.
+        let lock = DavLock::builder(path)
+            .token(token)
+            .timeout(Some(tm_limit(timeout)))
+            .shared(shared)
+            .deep(deep)
+            .build();
         Ok(lock)
     }
 
@@ -224,14 +224,13 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_ref().map(|s| s.as_str());
+            // for MOVE and COPY check if destination is locked,
+            // for MOVE also check if source path is locked.
+            let mut paths = vec![(&dest, true)];
             if method == DavMethod::Move {
-                // for MOVE check if source path is locked
-                if let Err(_l) = locksystem.check(&path, principal, false, true, t.clone()) {
-                    return Err(StatusCode::LOCKED.into());
-                }
+                paths.insert(0, (&path, true));
             }
-            // for MOVE and COPY check if destination is locked
-            if let Err(_l) = locksystem.check(&dest, principal, false, true, t) {
+            if let Err(_l) = locksystem.check_many(&paths, principal, false, t).await {
                 return Err(StatusCode::LOCKED.into());
             }
         }
@@ -253,7 +252,7 @@ impl crate::DavInner {
                     }
                     // should really do this per item, in case the delete partially fails. See TODO.md
                     if let Some(ref locksystem) = self.ls {
-                        let _ = locksystem.delete(&dest);
+                        let _ = locksystem.delete(&dest).await;
                     }
                 }
 
@@ -271,7 +270,7 @@ impl crate::DavInner {
                     // move and if successful, remove locks at old location.
                     if let Ok(_) = self.do_move(&path, &dest, &mut multierror).await {
                         if let Some(ref locksystem) = self.ls {
-                            locksystem.delete(&path).ok();
+                            locksystem.delete(&path).await.ok();
                         }
                         let s = if exists {
                             StatusCode::NO_CONTENT
@@ -137,7 +137,7 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_ref().map(|s| s.as_str());
-            if let Err(_l) = locksystem.check(&path, principal, false, true, t) {
+            if let Err(_l) = locksystem.check(&path, principal, false, true, t).await {
                 return Err(DavError::Status(StatusCode::LOCKED));
             }
         }
@@ -155,7 +155,7 @@ impl crate::DavInner {
                     // Done. Now delete the path in the locksystem as well.
                     // Should really do this per resource, in case the delete partially fails. See TODO.pm
                     if let Some(ref locksystem) = self.ls {
-                        locksystem.delete(&path).ok();
+                        locksystem.delete(&path).await.ok();
                     }
                     let _ = multierror.add_status(&path, StatusCode::NO_CONTENT).await;
                 }
@@ -8,12 +8,13 @@ use http::{Request, Response};
 use xmltree::{self, Element};
 
 use crate::body::Body;
-use crate::conditional::{dav_if_match, if_match};
+use crate::conditional::{dav_if_match, if_match_get_tokens};
 use crate::davheaders::{self, DavTimeout};
 use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::{FsError, OpenOptions};
 use crate::ls::*;
+use crate::ls_async::DynLockSystemAsync;
 use crate::util::MemBuffer;
 use crate::xmltree_ext::{self, ElementExt};
 use crate::DavResult;
@@ -28,10 +29,16 @@ impl crate::DavInner {
 
         let mut res = Response::new(Body::empty());
 
-        // path and meta
+        // path and meta. the path of the lock is stored as-is by the
+        // locksystem, so make sure that collections end in a slash and
+        // other resources don't. that way the lockroot is always the same.
         let mut path = self.path(&req);
         let meta = match self.fs.metadata(&path).await {
-            Ok(meta) => Some(self.fixpath(&mut res, &mut path, meta)),
+            Ok(meta) => {
+                let meta = self.fixpath(&mut res, &mut path, meta);
+                path.set_collection(meta.is_dir());
+                Some(meta)
+            },
             Err(_) => None,
         };
 
@@ -43,12 +50,14 @@ impl crate::DavInner {
                 return Err(SC::BAD_REQUEST.into());
             }
 
-            // try refresh
-            // FIXME: you can refresh a lock owned by someone else. is that OK?
+            // try refresh. the locksystem decides whether you can
+            // refresh a lock that is owned by someone else.
             let timeout = get_timeout(&req, true, false);
-            let lock = match locksystem.refresh(&path, &tokens[0], timeout) {
+            let principal = self.principal.as_deref();
+            let lock = match locksystem.refresh(&path, principal, &tokens[0], timeout).await {
                 Ok(lock) => lock,
-                Err(_) => return Err(SC::PRECONDITION_FAILED.into()),
+                Err(RefreshError::NotLocked) => return Err(SC::PRECONDITION_FAILED.into()),
+                Err(RefreshError::PrincipalMismatch) => return Err(SC::FORBIDDEN.into()),
             };
 
             // output result
@@ -64,16 +73,24 @@ impl crate::DavInner {
         }
 
         // handle Depth:
-        let deep = match req.headers().typed_get::<davheaders::Depth>() {
-            Some(davheaders::Depth::Infinity) | None => true,
-            Some(davheaders::Depth::Zero) => false,
-            _ => return Err(SC::BAD_REQUEST.into()),
+        let depth = match req.headers().get("Depth").map(|v| v.to_str()) {
+            Some(Ok(d)) => Some(d),
+            Some(Err(_)) => return Err(SC::BAD_REQUEST.into()),
+            None => None,
+        };
+        let depth = match parse_lock_depth(depth) {
+            Ok(depth) => depth,
+            Err(e) => {
+                debug!("LOCK: bad Depth header: {:?}", e);
+                return Err(SC::BAD_REQUEST.into());
+            },
         };
 
-        // handle the if-headers.
-        if let Some(s) = if_match(&req, meta.as_ref(), &self.fs, &self.ls, &path).await {
-            return Err(s.into());
-        }
+        // handle the if-headers, and remember the submitted lock tokens.
+        let tokens = match if_match_get_tokens(&req, meta.as_ref(), &self.fs, &self.ls, &path).await {
+            Ok(t) => t,
+            Err(s) => return Err(s.into()),
+        };
 
         // Cut & paste from method_put.rs ....
         let mut oo = OpenOptions::write();
@@ -100,7 +117,7 @@ impl crate::DavInner {
         }
 
         // decode Element.
-        let mut shared: Option<bool> = None;
+        let mut scope: Option<LockScope> = None;
         let mut owner: Option<Element> = None;
         let mut locktype = false;
 
@@ -109,8 +126,8 @@ impl crate::DavInner {
                 "lockscope" => {
                     let name = elem.child_elems_iter().find_map(|e| Some(e.name.as_ref()));
                     match name {
-                        Some("exclusive") => shared = Some(false),
-                        Some("shared") => shared = Some(true),
+                        Some("exclusive") => scope = Some(LockScope::Exclusive),
+                        Some("shared") => scope = Some(LockScope::Shared),
                         _ => return Err(DavError::XmlParseError),
                     }
                 },
@@ -131,17 +148,24 @@ impl crate::DavInner {
         }
 
         // sanity check.
-        if !shared.is_some() || !locktype {
-            return Err(DavError::XmlParseError);
+        let scope = match scope {
+            Some(s) if locktype => s,
+            _ => return Err(DavError::XmlParseError),
         };
-        let shared = shared.unwrap();
 
         // create lock
-        let timeout = get_timeout(&req, false, shared);
+        let timeout = get_timeout(req, false, scope.is_shared());
         let principal = self.principal.as_ref().map(|s| s.as_str());
-        let lock = match locksystem.lock(&path, principal, owner.as_ref(), timeout, shared, deep) {
+        let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        let fut = locksystem.lock(&path, principal, owner.as_ref(), timeout, scope, depth, t);
+        let lock = match fut.await {
             Ok(lock) => lock,
-            Err(_) => return Err(SC::LOCKED.into()),
+            Err(e) => {
+                if let LockError::Conflict(ref c) = e {
+                    debug!("LOCK {}: {}", path, c);
+                }
+                return Err(e.into());
+            },
         };
 
         // try to create file if it doesn't exist.
@@ -154,20 +178,20 @@ impl crate::DavInner {
                     } else {
                         SC::CONFLICT
                     };
-                    let _ = locksystem.unlock(&path, &lock.token);
+                    let _ = locksystem.unlock(&path, principal, &lock.token).await;
                     return Err(s.into());
                 },
                 Err(e) => {
-                    let _ = locksystem.unlock(&path, &lock.token);
+                    let _ = locksystem.unlock(&path, principal, &lock.token).await;
                     return Err(e.into());
                 },
             };
         }
 
         // output result
-        let lt = format!("<{}>", lock.token);
         let ct = "application/xml; charset=utf-8".to_owned();
-        res.headers_mut().typed_insert(davheaders::LockToken(lt));
+        res.headers_mut()
+            .typed_insert(davheaders::LockToken(lock.lock_token_header()));
         res.headers_mut().typed_insert(davheaders::ContentType(ct));
         if let None = meta {
             *res.status_mut() = SC::CREATED;
@@ -196,7 +220,7 @@ impl crate::DavInner {
             .headers()
             .typed_get::<davheaders::LockToken>()
             .ok_or(DavError::Status(SC::BAD_REQUEST))?;
-        let token = t.0.trim_matches(|c| c == '<' || c == '>');
+        let token = parse_lock_token_header(&t.0).ok_or(DavError::Status(SC::BAD_REQUEST))?;
 
         let mut res = Response::new(Body::empty());
 
@@ -205,17 +229,20 @@ impl crate::DavInner {
             self.fixpath(&mut res, &mut path, meta);
         }
 
-        match locksystem.unlock(&path, token) {
+        let principal = self.principal.as_deref();
+        match locksystem.unlock(&path, principal, &token).await {
             Ok(_) => {
                 *res.status_mut() = SC::NO_CONTENT;
                 Ok(res)
             },
-            Err(_) => Err(SC::CONFLICT.into()),
+            Err(UnlockError::NotLocked) => Err(SC::NOT_FOUND.into()),
+            Err(UnlockError::TokenMismatch) => Err(SC::CONFLICT.into()),
+            Err(UnlockError::PrincipalMismatch) => Err(SC::FORBIDDEN.into()),
         }
     }
 }
 
-pub(crate) fn list_lockdiscovery(ls: Option<&Box<dyn DavLockSystem>>, path: &DavPath) -> Element {
+pub(crate) async fn list_lockdiscovery(ls: Option<&DynLockSystemAsync>, path: &DavPath) -> Element {
     let mut elem = Element::new2("D:lockdiscovery");
 
     // must have a locksystem or bail
@@ -225,40 +252,50 @@ pub(crate) fn list_lockdiscovery(ls: Option<&Box<dyn DavLockSystem>>, path: &Dav
     };
 
     // list the locks.
-    let locks = locksystem.discover(path);
+    let locks = locksystem.discover(path).await;
     for lock in &locks {
         elem.push_element(build_lock_prop(lock, false));
     }
     elem
 }
 
-pub(crate) fn list_supportedlock(ls: Option<&Box<dyn DavLockSystem>>) -> Element {
+pub(crate) fn list_supportedlock(ls: Option<&DynLockSystemAsync>) -> Element {
     let mut elem = Element::new2("D:supportedlock");
 
     // must have a locksystem or bail
-    if ls.is_none() {
-        return elem;
+    let locksystem = match ls {
+        Some(ls) => ls,
+        None => return elem,
+    };
+
+    for entry in locksystem.supported_lock() {
+        elem.push_element(entry.to_lockentry_element());
     }
+    elem
+}
 
-    let mut entry = Element::new2("D:lockentry");
-    let mut scope = Element::new2("D:lockscope");
-    let mut ltype = Element::new2("D:locktype");
-    scope.push_element(Element::new2("D:exclusive"));
-    ltype.push_element(Element::new2("D:write"));
-    entry.push_element(scope);
-    entry.push_element(ltype);
-    elem.push_element(entry);
-
-    let mut entry = Element::new2("D:lockentry");
-    let mut scope = Element::new2("D:lockscope");
-    let mut ltype = Element::new2("D:locktype");
-    scope.push_element(Element::new2("D:shared"));
-    ltype.push_element(Element::new2("D:write"));
-    entry.push_element(scope);
-    entry.push_element(ltype);
-    elem.push_element(entry);
+// Invalid Depth: header on a LOCK request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DepthError {
+    // Depth: 1 is not allowed for LOCK (RFC4918, 9.10.3).
+    DepthOne,
+    // Not a valid Depth: value at all.
+    Invalid,
+}
 
-    elem
+// Map the Depth: header of a LOCK request to the "depth" parameter of
+// DavLockSystem::lock. No header means infinity.
+pub(crate) fn parse_lock_depth(header: Option<&str>) -> Result<LockDepth, DepthError> {
+    let header = match header {
+        Some(h) => h.trim(),
+        None => return Ok(LockDepth::Infinity),
+    };
+    match header {
+        "0" => Ok(LockDepth::Zero),
+        "1" => Err(DepthError::DepthOne),
+        h if h.eq_ignore_ascii_case("infinity") => Ok(LockDepth::Infinity),
+        _ => Err(DepthError::Invalid),
+    }
 }
 
 // process timeout header
@@ -286,44 +323,7 @@ fn get_timeout(req: &Request<()>, refresh: bool, shared: bool) -> Option<Duratio
 }
 
 fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
-    let mut actlock = Element::new2("D:activelock");
-
-    let mut elem = Element::new2("D:lockscope");
-    elem.push_element(match lock.shared {
-        false => Element::new2("D:exclusive"),
-        true => Element::new2("D:shared"),
-    });
-    actlock.push_element(elem);
-
-    let mut elem = Element::new2("D:locktype");
-    elem.push_element(Element::new2("D:write"));
-    actlock.push_element(elem);
-
-    actlock.push_element(
-        Element::new2("D:depth").text(
-            match lock.deep {
-                false => "0",
-                true => "Infinity",
-            }
-            .to_string(),
-        ),
-    );
-
-    actlock.push_element(Element::new2("D:timeout").text(match lock.timeout {
-        None => "Infinite".to_string(),
-        Some(d) => format!("Second-{}", d.as_secs()),
-    }));
-    let mut locktokenelem = Element::new2("D:locktoken");
-    locktokenelem.push_element(Element::new2("D:href").text(lock.token.clone()));
-    actlock.push_element(locktokenelem);
-
-    let mut lockroot = Element::new2("D:lockroot");
-    lockroot.push_element(Element::new2("D:href").text(lock.path.with_prefix().as_url_string()));
-    actlock.push_element(lockroot);
-
-    if let Some(ref o) = lock.owner {
-        actlock.push_element(o.clone());
-    }
+    let actlock = lock.to_activelock_element();
 
     if !full {
         return actlock;
@@ -336,3 +336,20 @@ fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
 
     prop
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_depth() {
+        assert_eq!(parse_lock_depth(None), Ok(LockDepth::Infinity));
+        assert_eq!(parse_lock_depth(Some("0")), Ok(LockDepth::Zero));
+        assert_eq!(parse_lock_depth(Some("infinity")), Ok(LockDepth::Infinity));
+        assert_eq!(parse_lock_depth(Some("Infinity")), Ok(LockDepth::Infinity));
+        assert_eq!(parse_lock_depth(Some(" 0 ")), Ok(LockDepth::Zero));
+        assert_eq!(parse_lock_depth(Some("1")), Err(DepthError::DepthOne));
+        assert_eq!(parse_lock_depth(Some("2")), Err(DepthError::Invalid));
+        assert_eq!(parse_lock_depth(Some("")), Err(DepthError::Invalid));
+    }
+}
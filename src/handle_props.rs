@@ -23,7 +23,7 @@ use crate::davpath::*;
 use crate::errors::*;
 use crate::fs::*;
 use crate::handle_lock::{list_lockdiscovery, list_supportedlock};
-use crate::ls::*;
+use crate::ls_async::DynLockSystemAsync;
 use crate::util::MemBuffer;
 use crate::util::{dav_xml_error, systemtime_to_httpdate, systemtime_to_rfc3339};
 use crate::{DavInner, DavResult};
@@ -102,7 +102,7 @@ struct PropWriter {
     name:      String,
     props:     Vec<Element>,
     fs:        Box<dyn DavFileSystem>,
-    ls:        Option<Box<dyn DavLockSystem>>,
+    ls:        Option<DynLockSystemAsync>,
     useragent: String,
     q_cache:   QuotaCache,
 }
@@ -395,7 +395,7 @@ impl DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_ref().map(|s| s.as_str());
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
+            if let Err(_l) = locksystem.check(&path, principal, false, false, t).await {
                 return Err(StatusCode::LOCKED.into());
             }
         }
@@ -494,7 +494,7 @@ impl PropWriter {
         name: &str,
         mut props: Vec<Element>,
         fs: &Box<dyn DavFileSystem>,
-        ls: Option<&Box<dyn DavLockSystem>>,
+        ls: Option<&DynLockSystemAsync>,
     ) -> DavResult<PropWriter>
     {
         let contenttype = "application/xml; charset=utf-8".parse().unwrap();
@@ -726,7 +726,7 @@ impl PropWriter {
                     "lockdiscovery" => {
                         return Ok(StatusElement {
                             status:  StatusCode::OK,
-                            element: list_lockdiscovery(self.ls.as_ref(), path),
+                            element: list_lockdiscovery(self.ls.as_ref(), path).await,
                         });
                     },
                     "quota-available-bytes" => {
@@ -801,7 +801,7 @@ impl PropWriter {
                         //if meta.permissions().readonly() {
                         //    attr |= 0x0001;
                         //}
-                        if path.file_name().starts_with(b".") {
+                        if matches!(path.file_name(), Some(n) if n.starts_with(b".")) {
                             attr |= 0x0002;
                         }
                         if meta.is_dir() {
@@ -163,7 +163,7 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_ref().map(|s| s.as_str());
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
+            if let Err(_l) = locksystem.check(&path, principal, false, false, t).await {
                 return Err(DavError::StatusClose(SC::LOCKED));
             }
         }
@@ -0,0 +1,155 @@
+//! Parser for the WebDAV `If:` request header (RFC4918, 10.4).
+//!
+//! The header consists of one or more lists of conditions. A list
+//! can be tagged with the resource it applies to, if not, it applies
+//! to the resource in the request URL. A tag applies to all lists that
+//! follow it, up until the next tag. The conditions in a list are
+//! ANDed, the lists are ORed.
+//!
+//! The handler evaluates the `If:` header itself. This module is for
+//! code outside of the handler that needs the same information, for
+//! example to pass the submitted lock tokens to `DavLockSystem::check`:
+//!
+//! ```
+//! use webdav_handler::ifheader::parse_if_header;
+//!
+//! let hdr = parse_if_header(r#"<http://x.yz/a> (<urn:uuid:1234> ["etag"])"#).unwrap();
+//! assert_eq!(hdr.lists[0].resource.as_deref(), Some("http://x.yz/a"));
+//! assert_eq!(hdr.state_tokens(), vec!["urn:uuid:1234"]);
+//! ```
+use std::error::Error;
+use std::fmt;
+
+use headers::Header;
+use http::header::HeaderValue;
+
+use crate::davheaders;
+
+/// A parsed `If:` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfHeader {
+    /// The lists of conditions, in the order they appear in the header.
+    pub lists: Vec<IfList>,
+}
+
+/// A list of conditions, all of which have to be true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfList {
+    /// The resource tag, as it appears in the header. `None` if the list
+    /// applies to the resource in the request URL.
+    pub resource:   Option<String>,
+    /// The conditions.
+    pub conditions: Vec<IfCondition>,
+}
+
+/// A single condition, optionally negated with `Not`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfCondition {
+    /// Set if the condition was negated.
+    pub not:  bool,
+    /// The state token or entity tag.
+    pub item: IfItem,
+}
+
+/// What a condition tests for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfItem {
+    /// A state token, like a lock token, without the angle brackets.
+    StateToken(String),
+    /// An entity tag, without the square brackets, e.g. `W/"1234"`.
+    EntityTag(String),
+}
+
+/// Error returned by `parse_if_header` if the header is not valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfParseError;
+
+impl fmt::Display for IfParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid If header")
+    }
+}
+
+impl Error for IfParseError {}
+
+impl IfHeader {
+    /// All state tokens in the header, negated or not, in the order
+    /// they appear in.
+    pub fn state_tokens(&self) -> Vec<&str> {
+        self.lists
+            .iter()
+            .flat_map(|l| l.conditions.iter())
+            .filter_map(|c| {
+                match c.item {
+                    IfItem::StateToken(ref t) => Some(t.as_str()),
+                    IfItem::EntityTag(_) => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parse the value of an `If:` header.
+pub fn parse_if_header(s: &str) -> Result<IfHeader, IfParseError> {
+    let value = HeaderValue::from_str(s).map_err(|_| IfParseError)?;
+    let hdr = davheaders::If::decode(&mut std::iter::once(&value)).map_err(|_| IfParseError)?;
+    let lists = hdr
+        .0
+        .into_iter()
+        .map(|l| {
+            let conditions = l
+                .conditions
+                .into_iter()
+                .map(|c| {
+                    let item = match c.item {
+                        davheaders::IfItem::StateToken(t) => IfItem::StateToken(t),
+                        davheaders::IfItem::ETag(e) => IfItem::EntityTag(e.as_str().to_string()),
+                    };
+                    IfCondition { not: c.not, item }
+                })
+                .collect();
+            IfList {
+                resource: l.resource_tag.map(|u| u.to_string()),
+                conditions,
+            }
+        })
+        .collect();
+    Ok(IfHeader { lists })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let val = r#"<http://x.yz/a> (<urn:x:1> Not ["1"]) (Not <urn:x:2>) <http://x.yz/b> ([W/"2"])"#;
+        let hdr = parse_if_header(val).unwrap();
+        assert_eq!(hdr.lists.len(), 3);
+        assert_eq!(hdr.lists[0].resource.as_deref(), Some("http://x.yz/a"));
+        assert_eq!(hdr.lists[1].resource.as_deref(), Some("http://x.yz/a"));
+        assert_eq!(hdr.lists[2].resource.as_deref(), Some("http://x.yz/b"));
+        assert_eq!(
+            hdr.lists[0].conditions,
+            vec![
+                IfCondition {
+                    not:  false,
+                    item: IfItem::StateToken("urn:x:1".to_string()),
+                },
+                IfCondition {
+                    not:  true,
+                    item: IfItem::EntityTag("\"1\"".to_string()),
+                },
+            ]
+        );
+        assert!(hdr.lists[1].conditions[0].not);
+        assert_eq!(hdr.state_tokens(), vec!["urn:x:1", "urn:x:2"]);
+
+        let hdr = parse_if_header("(<urn:x:1>)").unwrap();
+        assert_eq!(hdr.lists[0].resource, None);
+
+        for bad in &["", "(", "()", "(<urn:x:1>", "<http://x.yz/>", "(nocolon)"] {
+            assert_eq!(parse_if_header(bad), Err(IfParseError), "{}", bad);
+        }
+    }
+}
@@ -63,6 +63,8 @@
 //! - [`MemLs`]: ephemeral in-memory locksystem.
 //! - [`FakeLs`]: fake locksystem. just enough LOCK/UNLOCK support for macOS/Windows.
 //!
+//! and [`QuotaLs`], an adapter that limits the number of locks per principal.
+//!
 //! ## Example.
 //!
 //! Example server using [hyper] that serves the /tmp directory in r/w mode. You should be
@@ -113,6 +115,7 @@
 //! [`MemFs`]: memfs/index.html
 //! [`LocalFs`]: localfs/index.html
 //! [`FakeLs`]: fakels/index.html
+//! [`QuotaLs`]: quotals/index.html
 //! [actix-compat]: actix/index.html
 //! [warp-compat]: warp/index.html
 //! [README_litmus]: https://github.com/miquels/webdav-handler-rs/blob/master/README.litmus-test.md
@@ -155,10 +158,13 @@ pub mod body;
 pub mod davpath;
 pub mod fakels;
 pub mod fs;
+pub mod ifheader;
 pub mod localfs;
 pub mod ls;
+pub mod ls_async;
 pub mod memfs;
 pub mod memls;
+pub mod quotals;
 
 #[cfg(any(docsrs, feature = "actix-compat"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "actix-compat")))]
@@ -5,54 +5,766 @@
 //! Both of them do not do any I/O, all methods return instantly.
 //!
 //! If ever a locksystem gets built that does I/O (to a filesystem,
-//! a database, or over the network) we'll need to revisit this.
+//! a database, or over the network) it can implement the async
+//! version of the trait in [`ls_async`](../ls_async/index.html) instead.
 //!
 use crate::davpath::DavPath;
-use std::fmt::Debug;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
+use uuid::Uuid;
 use xmltree::Element;
 
+use crate::xmltree_ext::ElementExt;
+
+#[cfg(feature = "memls-serde")]
+use serde::{Deserialize, Serialize};
+
+/// A lock token.
+///
+/// Lock tokens are URIs (RFC4918, section 6.5), usually `urn:uuid:...`
+/// or `opaquelocktoken:...`. `LockToken::parse` checks that a string
+/// looks like an absolute URI: a scheme, a colon, and a non-empty rest
+/// without whitespace or angle brackets.
+///
+/// It dereferences to `&str`, and can be compared with strings directly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "memls-serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct LockToken(String);
+
+/// Error returned by `LockToken::parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLockToken;
+
+impl std::error::Error for InvalidLockToken {}
+
+impl fmt::Display for InvalidLockToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid lock token")
+    }
+}
+
+impl LockToken {
+    /// Check that `token` is a valid lock token.
+    pub fn parse(token: &str) -> Result<LockToken, InvalidLockToken> {
+        let (scheme, rest) = match token.find(':') {
+            Some(n) => (&token[..n], &token[n + 1..]),
+            None => return Err(InvalidLockToken),
+        };
+        let mut chars = scheme.chars();
+        if !matches!(chars.next(), Some(c) if c.is_ascii_alphabetic()) ||
+            !chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        {
+            return Err(InvalidLockToken);
+        }
+        let bad = |c: char| c.is_whitespace() || c.is_control() || c == '<' || c == '>';
+        if rest.is_empty() || rest.chars().any(bad) {
+            return Err(InvalidLockToken);
+        }
+        Ok(LockToken(token.to_string()))
+    }
+
+    /// Generate a new, unique, `urn:uuid:` token.
+    pub fn generate() -> LockToken {
+        LockToken(Uuid::new_v4().urn().to_string())
+    }
+
+    /// The token as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Unwrap the token.
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl std::ops::Deref for LockToken {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+// The hash of a LockToken is the hash of the string, so this is allowed.
+impl std::borrow::Borrow<str> for LockToken {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for LockToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for LockToken {
+    type Err = InvalidLockToken;
+
+    fn from_str(s: &str) -> Result<LockToken, InvalidLockToken> {
+        LockToken::parse(s)
+    }
+}
+
+impl From<LockToken> for String {
+    fn from(token: LockToken) -> String {
+        token.0
+    }
+}
+
+impl PartialEq<str> for LockToken {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for LockToken {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for LockToken {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl PartialEq<LockToken> for str {
+    fn eq(&self, other: &LockToken) -> bool {
+        self == other.0
+    }
+}
+
+impl PartialEq<LockToken> for &str {
+    fn eq(&self, other: &LockToken) -> bool {
+        *self == other.0
+    }
+}
+
+impl PartialEq<LockToken> for String {
+    fn eq(&self, other: &LockToken) -> bool {
+        *self == other.0
+    }
+}
+
+/// Scope of a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockScope {
+    /// No other lock can be held on the same resource.
+    Exclusive,
+    /// Other shared locks can be held on the same resource.
+    Shared,
+}
+
+impl LockScope {
+    /// `Shared` if `shared` is true, `Exclusive` otherwise.
+    pub fn from_shared(shared: bool) -> LockScope {
+        if shared {
+            LockScope::Shared
+        } else {
+            LockScope::Exclusive
+        }
+    }
+
+    /// Is this the shared scope?
+    pub fn is_shared(self) -> bool {
+        self == LockScope::Shared
+    }
+}
+
+/// Depth of a lock: just the resource, or the resource and everything
+/// below it. Depth 1 does not exist for locks (RFC4918, 9.10.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockDepth {
+    /// `Depth: 0`.
+    Zero,
+    /// `Depth: infinity`, a deep lock.
+    Infinity,
+}
+
+impl LockDepth {
+    /// `Infinity` if `deep` is true, `Zero` otherwise.
+    pub fn from_deep(deep: bool) -> LockDepth {
+        if deep {
+            LockDepth::Infinity
+        } else {
+            LockDepth::Zero
+        }
+    }
+
+    /// Is this a deep lock?
+    pub fn is_infinity(self) -> bool {
+        self == LockDepth::Infinity
+    }
+}
+
 /// Type of the locks returned by DavLockSystem methods.
+///
+/// Locks are compared and hashed by their token only, all other
+/// fields (timeout, owner, etc) are ignored. Tokens are unique,
+/// so this is what you want when deduplicating locks or storing
+/// them in a `HashSet`.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "memls-serde", derive(Serialize, Deserialize))]
 pub struct DavLock {
     /// Token.
-    pub token:      String,
+    pub token:             LockToken,
     /// Path/
-    pub path:       DavPath,
+    #[cfg_attr(feature = "memls-serde", serde(with = "path_url"))]
+    pub path:              DavPath,
     /// Principal.
-    pub principal:  Option<String>,
+    pub principal:         Option<String>,
     /// Owner.
-    pub owner:      Option<Element>,
+    #[cfg_attr(feature = "memls-serde", serde(with = "owner_xml"))]
+    pub owner:             Option<Element>,
     /// When the lock turns stale (absolute).
-    pub timeout_at: Option<SystemTime>,
+    pub timeout_at:        Option<SystemTime>,
     /// When the lock turns stale (relative).
-    pub timeout:    Option<Duration>,
-    /// Shared.
-    pub shared:     bool,
-    /// Deep.
-    pub deep:       bool,
+    pub timeout:           Option<Duration>,
+    /// Scope.
+    #[cfg_attr(feature = "memls-serde", serde(rename = "shared", with = "scope_bool"))]
+    pub scope:             LockScope,
+    /// Depth.
+    #[cfg_attr(feature = "memls-serde", serde(rename = "deep", with = "depth_bool"))]
+    pub depth:             LockDepth,
+    /// When the lock was created.
+    pub created_at:        SystemTime,
+    /// When the lock was last refreshed. Same as `created_at` if it
+    /// never was.
+    #[cfg_attr(feature = "memls-serde", serde(default = "unix_epoch"))]
+    pub last_refreshed_at: SystemTime,
+    /// Extra data that the application attached to the lock, for
+    /// example the address of the client. The locksystem only stores it.
+    #[cfg_attr(feature = "memls-serde", serde(default))]
+    pub extra:             HashMap<String, String>,
+}
+
+impl PartialEq for DavLock {
+    fn eq(&self, other: &DavLock) -> bool {
+        self.token == other.token
+    }
+}
+
+impl Eq for DavLock {}
+
+impl Hash for DavLock {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.token.hash(state);
+    }
+}
+
+/// Parse the value of a `Lock-Token` request header (`<urn:uuid:...>`)
+/// and return the token without the angle brackets.
+///
+/// Returns `None` if the brackets are missing or there is nothing
+/// between them.
+pub fn parse_lock_token_header(value: &str) -> Option<String> {
+    let token = value.trim().strip_prefix('<')?.strip_suffix('>')?;
+    if token.is_empty() || token.contains(['<', '>']) {
+        return None;
+    }
+    Some(token.to_string())
+}
+
+/// Builder for a `DavLock`, returned by `DavLock::builder`.
+#[derive(Debug, Clone)]
+pub struct DavLockBuilder {
+    lock:     DavLock,
+    deadline: Option<SystemTime>,
+}
+
+impl DavLock {
+    /// Start building a new lock on `path`.
+    ///
+    /// The defaults are a random `urn:uuid:` token, no principal, no owner,
+    /// an infinite timeout, exclusive, depth 0, created now.
+    pub fn builder(path: &DavPath) -> DavLockBuilder {
+        let now = SystemTime::now();
+        DavLockBuilder {
+            lock: DavLock {
+                token:             LockToken::generate(),
+                path:              path.clone(),
+                principal:         None,
+                owner:             None,
+                timeout_at:        None,
+                timeout:           None,
+                scope:             LockScope::Exclusive,
+                depth:             LockDepth::Zero,
+                created_at:        now,
+                last_refreshed_at: now,
+                extra:             HashMap::new(),
+            },
+            deadline: None,
+        }
+    }
+
+    /// Is this a shared lock?
+    pub fn is_shared(&self) -> bool {
+        self.scope.is_shared()
+    }
+
+    /// Is this a deep (Depth: infinity) lock?
+    pub fn is_deep(&self) -> bool {
+        self.depth.is_infinity()
+    }
+
+    /// How long until this lock expires, seen from `now`. Zero if it has
+    /// already expired, `None` if it never expires.
+    pub fn remaining(&self, now: SystemTime) -> Option<Duration> {
+        self.timeout_at
+            .map(|t| t.duration_since(now).unwrap_or(Duration::new(0, 0)))
+    }
+
+    /// Does this lock cover `path`? A lock covers the path it was placed
+    /// on, and if it is deep, everything below it as well.
+    pub fn applies_to(&self, path: &DavPath) -> bool {
+        match path.strip_prefix(&self.path) {
+            Some(rest) => self.is_deep() || rest.as_bytes() == b"/",
+            None => false,
+        }
+    }
+
+    /// The value for the `Lock-Token` response header, the token
+    /// between angle brackets: `<urn:uuid:...>`.
+    pub fn lock_token_header(&self) -> String {
+        format!("<{}>", self.token)
+    }
+
+    /// Generate the `D:activelock` XML element that describes this lock,
+    /// as used in the `lockdiscovery` property.
+    pub fn to_activelock_element(&self) -> Element {
+        let mut actlock = Element::new2("D:activelock");
+
+        let mut elem = Element::new2("D:lockscope");
+        elem.push_element(match self.scope {
+            LockScope::Exclusive => Element::new2("D:exclusive"),
+            LockScope::Shared => Element::new2("D:shared"),
+        });
+        actlock.push_element(elem);
+
+        let mut elem = Element::new2("D:locktype");
+        elem.push_element(Element::new2("D:write"));
+        actlock.push_element(elem);
+
+        actlock.push_element(
+            Element::new2("D:depth").text(
+                match self.depth {
+                    LockDepth::Zero => "0",
+                    LockDepth::Infinity => "Infinity",
+                }
+                .to_string(),
+            ),
+        );
+
+        actlock.push_element(Element::new2("D:timeout").text(match self.timeout {
+            None => "Infinite".to_string(),
+            Some(d) => format!("Second-{}", d.as_secs()),
+        }));
+        let mut locktokenelem = Element::new2("D:locktoken");
+        locktokenelem.push_element(Element::new2("D:href").text(self.token.to_string()));
+        actlock.push_element(locktokenelem);
+
+        let mut lockroot = Element::new2("D:lockroot");
+        lockroot.push_element(Element::new2("D:href").text(self.path.with_prefix().as_url_string()));
+        actlock.push_element(lockroot);
+
+        if let Some(ref o) = self.owner {
+            actlock.push_element(o.clone());
+        }
+
+        actlock
+    }
+}
+
+impl DavLockBuilder {
+    /// Lock token. This is not validated, use `LockToken::parse`
+    /// first if the token comes from somewhere else.
+    pub fn token<S: Into<String>>(self, token: S) -> Self {
+        let mut this = self;
+        this.lock.token = LockToken(token.into());
+        this
+    }
+
+    /// Principal.
+    pub fn principal(self, principal: Option<&str>) -> Self {
+        let mut this = self;
+        this.lock.principal = principal.map(|s| s.to_string());
+        this
+    }
+
+    /// Owner.
+    pub fn owner(self, owner: Option<&Element>) -> Self {
+        let mut this = self;
+        this.lock.owner = owner.cloned();
+        this
+    }
+
+    /// Timeout. `None` means infinite.
+    pub fn timeout(self, timeout: Option<Duration>) -> Self {
+        let mut this = self;
+        this.lock.timeout = timeout;
+        this.deadline = None;
+        this
+    }
+
+    /// Expire at `deadline`, instead of after a timeout. The expiry time
+    /// is used as-is, the timeout is calculated from it.
+    pub fn timeout_at(self, deadline: SystemTime) -> Self {
+        let mut this = self;
+        this.deadline = Some(deadline);
+        this
+    }
+
+    /// Extra data, see `DavLock::extra`.
+    pub fn extra(self, extra: HashMap<String, String>) -> Self {
+        let mut this = self;
+        this.lock.extra = extra;
+        this
+    }
+
+    /// Scope. The default is exclusive.
+    pub fn scope(self, scope: LockScope) -> Self {
+        let mut this = self;
+        this.lock.scope = scope;
+        this
+    }
+
+    /// Depth. The default is 0.
+    pub fn depth(self, depth: LockDepth) -> Self {
+        let mut this = self;
+        this.lock.depth = depth;
+        this
+    }
+
+    /// Shared (`true`) or exclusive (`false`) lock. Same as `scope`.
+    pub fn shared(self, shared: bool) -> Self {
+        self.scope(LockScope::from_shared(shared))
+    }
+
+    /// Deep (Depth: infinity) lock. Same as `depth`.
+    pub fn deep(self, deep: bool) -> Self {
+        self.depth(LockDepth::from_deep(deep))
+    }
+
+    /// Creation time. The expiry time is calculated from this.
+    /// This also sets the time of the last refresh.
+    pub fn created_at(self, created_at: SystemTime) -> Self {
+        let mut this = self;
+        this.lock.created_at = created_at;
+        this.lock.last_refreshed_at = created_at;
+        this
+    }
+
+    /// Build the lock.
+    ///
+    /// If the timeout is so large that the expiry time cannot be
+    /// represented, the lock never expires.
+    pub fn build(self) -> DavLock {
+        let mut lock = self.lock;
+        match self.deadline {
+            Some(deadline) => {
+                let timeout = deadline.duration_since(lock.created_at).unwrap_or(Duration::new(0, 0));
+                lock.timeout = Some(timeout);
+                lock.timeout_at = Some(deadline);
+            },
+            None => lock.timeout_at = lock.timeout.and_then(|d| lock.created_at.checked_add(d)),
+        }
+        lock
+    }
+}
+
+/// A kind of lock that a locksystem supports, as returned by
+/// `DavLockSystem::supported_lock`. The lock type is always `write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupportedLockEntry {
+    /// Shared (`true`) or exclusive (`false`) lock.
+    pub shared: bool,
+}
+
+impl SupportedLockEntry {
+    /// Generate the `D:lockentry` XML element for this entry, as used in
+    /// the `supportedlock` property.
+    pub fn to_lockentry_element(&self) -> Element {
+        let mut entry = Element::new2("D:lockentry");
+        let mut scope = Element::new2("D:lockscope");
+        scope.push_element(match self.shared {
+            false => Element::new2("D:exclusive"),
+            true => Element::new2("D:shared"),
+        });
+        let mut ltype = Element::new2("D:locktype");
+        ltype.push_element(Element::new2("D:write"));
+        entry.push_element(scope);
+        entry.push_element(ltype);
+        entry
+    }
+}
+
+/// Where a conflicting lock is, relative to the path that was to be locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictRelation {
+    /// The lock is a deep lock on a parent collection.
+    Ancestor,
+    /// The lock is on the path itself.
+    Self_,
+    /// The lock is on a member of the collection that was to be deep-locked.
+    Descendant,
+}
+
+/// The lock that is in the way, returned in `LockError::Conflict`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockConflict {
+    /// The conflicting lock.
+    pub lock:     DavLock,
+    /// Where it is.
+    pub relation: ConflictRelation,
+}
+
+impl fmt::Display for LockConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let relation = match self.relation {
+            ConflictRelation::Ancestor => "parent",
+            ConflictRelation::Self_ => "resource",
+            ConflictRelation::Descendant => "member",
+        };
+        write!(f, "{} is locked ({})", self.lock.path, relation)
+    }
+}
+
+/// Errors generated by `DavLockSystem::lock`.
+#[derive(Debug, Clone)]
+pub enum LockError {
+    /// The path is locked by another, conflicting, lock (423)
+    Conflict(Box<LockConflict>),
+    /// The resource already holds the maximum number of locks (423)
+    TooManyLocks,
+    /// The locksystem already holds the maximum number of locks (507)
+    StorageFull,
+    /// The path has too many segments (414)
+    PathTooDeep,
+    /// No valid (non-empty, unique) lock token could be generated (500)
+    InvalidToken,
+    /// Locks with an infinite timeout are not allowed (400)
+    InfiniteTimeout,
+    /// The locksystem is in an inconsistent state (500)
+    Internal,
+    /// The principal already holds the maximum number of locks (507)
+    QuotaExceeded,
+    /// The owner element is too large (413)
+    OwnerTooLarge,
+    /// The path contains control characters (400)
+    InvalidPath,
+}
+
+/// Errors generated by `DavLockSystem::unlock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnlockError {
+    /// There is no lock on the path at all (404)
+    NotLocked,
+    /// The path is locked, but not with this token, or the path is
+    /// not the root of the lock (409)
+    TokenMismatch,
+    /// The lock belongs to another principal (403)
+    PrincipalMismatch,
+}
+
+/// Errors generated by `DavLockSystem::refresh`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshError {
+    /// There is no lock with this token on the path (412)
+    NotLocked,
+    /// The lock belongs to another principal (403)
+    PrincipalMismatch,
+}
+
+// (De)serialize the owner element as a string of XML.
+#[cfg(feature = "memls-serde")]
+mod owner_xml {
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+    use xmltree::{Element, EmitterConfig};
+
+    pub fn serialize<S: Serializer>(owner: &Option<Element>, serializer: S) -> Result<S::Ok, S::Error> {
+        let xml = match owner {
+            Some(elem) => {
+                let mut buf = Vec::new();
+                let config = EmitterConfig::new().write_document_declaration(false);
+                elem.write_with_config(&mut buf, config)
+                    .map_err(S::Error::custom)?;
+                Some(String::from_utf8(buf).map_err(S::Error::custom)?)
+            },
+            None => None,
+        };
+        xml.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Element>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(xml) => Ok(Some(Element::parse(xml.as_bytes()).map_err(D::Error::custom)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// (De)serialize the path as its URL string. A path with a prefix is
+// stored as the URL string with the prefix, plus the prefix itself.
+#[cfg(feature = "memls-serde")]
+mod path_url {
+    use crate::davpath::DavPath;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Url(String),
+        WithPrefix { url: String, prefix: String },
+    }
+
+    pub fn serialize<S: Serializer>(path: &DavPath, serializer: S) -> Result<S::Ok, S::Error> {
+        let saved = match path.prefix() {
+            "" => Saved::Url(path.as_url_string()),
+            prefix => {
+                Saved::WithPrefix {
+                    url:    path.with_prefix().as_url_string(),
+                    prefix: prefix.to_string(),
+                }
+            },
+        };
+        saved.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DavPath, D::Error> {
+        let (url, path) = match Saved::deserialize(deserializer)? {
+            Saved::Url(url) => {
+                let path = DavPath::new(&url);
+                (url, path)
+            },
+            Saved::WithPrefix { url, prefix } => {
+                let path = DavPath::from_str_and_prefix(&url, &prefix);
+                (url, path)
+            },
+        };
+        path.map_err(|e| D::Error::custom(format!("invalid path {}: {}", url, e)))
+    }
+}
+
+// Locks saved before last_refreshed_at existed do not have it.
+// MemLs::load_from replaces this with created_at.
+#[cfg(feature = "memls-serde")]
+fn unix_epoch() -> SystemTime {
+    SystemTime::UNIX_EPOCH
+}
+
+// (De)serialize the scope and depth as booleans, like they were
+// stored before the LockScope and LockDepth enums existed.
+#[cfg(feature = "memls-serde")]
+mod scope_bool {
+    use super::LockScope;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(scope: &LockScope, serializer: S) -> Result<S::Ok, S::Error> {
+        scope.is_shared().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LockScope, D::Error> {
+        bool::deserialize(deserializer).map(LockScope::from_shared)
+    }
+}
+
+#[cfg(feature = "memls-serde")]
+mod depth_bool {
+    use super::LockDepth;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(depth: &LockDepth, serializer: S) -> Result<S::Ok, S::Error> {
+        depth.is_infinity().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LockDepth, D::Error> {
+        bool::deserialize(deserializer).map(LockDepth::from_deep)
+    }
 }
 
 /// The trait that defines a locksystem.
 pub trait DavLockSystem: Debug + Sync + Send + BoxCloneLs {
     /// Lock a node. Returns `Ok(new_lock)` if succeeded,
-    /// or `Err(conflicting_lock)` if failed.
+    /// or `Err(LockError::Conflict(conflict))` if failed.
+    ///
+    /// The handler passes the path of an existing collection with a
+    /// trailing slash, and the path of any other existing resource without
+    /// one, so `path` can be stored as the lock root as-is. `discover`
+    /// then returns the same lock root no matter how the client spelled
+    /// the path in the LOCK request.
+    ///
+    /// `submitted_tokens` are the lock tokens that the client submitted
+    /// in the `If` header. A locksystem can use them to allow a client
+    /// to take out a new lock inside a tree it already has locked.
+    ///
+    /// This is the single source of truth: checking for conflicts and
+    /// creating the lock must be one atomic step, so that two clients
+    /// cannot both get a conflicting lock on the same path. A check up
+    /// front, like `check` or `MemLs::would_lock`, is only advisory.
+    #[allow(clippy::too_many_arguments)]
     fn lock(
         &self,
         path: &DavPath,
         principal: Option<&str>,
         owner: Option<&Element>,
         timeout: Option<Duration>,
-        shared: bool,
-        deep: bool,
-    ) -> Result<DavLock, DavLock>;
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>;
 
-    /// Unlock a node. Returns `Ok(())` if succeeded, `Err (())` if failed
-    /// (because lock doesn't exist)
-    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()>;
+    /// Unlock a node. Returns `Ok(())` if succeeded, or an `UnlockError`
+    /// that says why it failed.
+    ///
+    /// `principal` is the authenticated user that does the unlock. A
+    /// locksystem can use it to only allow the creator of a lock to unlock it.
+    fn unlock(&self, path: &DavPath, principal: Option<&str>, token: &str) -> Result<(), UnlockError>;
 
-    /// Refresh lock. Returns updated lock if succeeded.
-    fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()>;
+    /// Refresh lock. Returns the updated lock if succeeded, or a
+    /// `RefreshError` that says why it failed.
+    ///
+    /// `principal` is as in `unlock`.
+    ///
+    /// Only the timeout of the lock changes. Its scope (`shared`), depth
+    /// (`deep`), owner, principal and token stay the same; a client that
+    /// wants a different scope has to UNLOCK and LOCK again. An
+    /// implementation must not change them either, and a future version
+    /// of this method that takes the scope as well will reject a refresh
+    /// that asks for a different one.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use webdav_handler::davpath::DavPath;
+    /// use webdav_handler::ls::{DavLockSystem, LockDepth, LockScope};
+    /// use webdav_handler::memls::MemLs;
+    ///
+    /// let ls = MemLs::new();
+    /// let path = DavPath::new("/a/").unwrap();
+    /// let timeout = Some(Duration::from_secs(60));
+    /// let (scope, depth) = (LockScope::Shared, LockDepth::Infinity);
+    /// let lock = ls.lock(&path, None, None, timeout, scope, depth, vec![]).unwrap();
+    ///
+    /// let timeout = Some(Duration::from_secs(120));
+    /// let refreshed = ls.refresh(&path, None, &lock.token, timeout).unwrap();
+    /// assert_eq!(refreshed.timeout, timeout);
+    /// assert_eq!(refreshed.scope, LockScope::Shared);
+    /// assert_eq!(refreshed.depth, LockDepth::Infinity);
+    /// assert_eq!(refreshed.token, lock.token);
+    /// ```
+    fn refresh(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, RefreshError>;
 
     /// Check if node is locked and if so, if we own all the locks.
     /// If not, returns as Err one conflicting lock.
@@ -65,11 +777,67 @@ pub trait DavLockSystem: Debug + Sync + Send + BoxCloneLs {
         submitted_tokens: Vec<&str>,
     ) -> Result<(), DavLock>;
 
+    /// Like `check`, but returns all conflicting locks instead of just one.
+    ///
+    /// The default implementation calls `check`, so it returns at most one lock.
+    fn check_all(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), Vec<DavLock>>
+    {
+        self.check(path, principal, ignore_principal, deep, submitted_tokens)
+            .map_err(|l| vec![l])
+    }
+
+    /// Check a number of paths at once, e.g. the source and destination
+    /// of a MOVE. The `bool` says whether to also check below the path,
+    /// like `deep` in `check`. Returns the first conflicting lock.
+    ///
+    /// The default implementation calls `check` for every path. A
+    /// locksystem can override it to check all paths atomically.
+    fn check_many(
+        &self,
+        paths: &[(&DavPath, bool)],
+        principal: Option<&str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        for &(path, deep) in paths {
+            self.check(path, principal, ignore_principal, deep, submitted_tokens.clone())?;
+        }
+        Ok(())
+    }
+
     /// Find and return all locks that cover a given path.
     fn discover(&self, path: &DavPath) -> Vec<DavLock>;
 
     /// Delete all locks at this path and below (after MOVE or DELETE)
     fn delete(&self, path: &DavPath) -> Result<(), ()>;
+
+    /// Does the lock with this token still exist, wherever it is now?
+    ///
+    /// `path` is where the lock was created. The default implementation
+    /// looks for the token in `discover(path)`, so a locksystem that can
+    /// move locks to another path should override it.
+    fn has_token(&self, token: &str, path: &DavPath) -> bool {
+        self.discover(path).iter().any(|l| l.token == token)
+    }
+
+    /// The kinds of locks this locksystem supports, for the
+    /// `supportedlock` property.
+    ///
+    /// The default implementation returns exclusive and shared write locks.
+    fn supported_lock(&self) -> Vec<SupportedLockEntry> {
+        vec![
+            SupportedLockEntry { shared: false },
+            SupportedLockEntry { shared: true },
+        ]
+    }
 }
 
 #[doc(hidden)]
@@ -80,7 +848,7 @@ pub trait BoxCloneLs {
 // generic Clone, calls implementation-specific box_clone().
 impl Clone for Box<dyn DavLockSystem> {
     fn clone(&self) -> Box<dyn DavLockSystem> {
-        self.box_clone()
+        (**self).box_clone()
     }
 }
 
@@ -91,3 +859,293 @@ impl<LS: Clone + DavLockSystem + 'static> BoxCloneLs for LS {
         Box::new((*self).clone())
     }
 }
+
+/// A shareable locksystem trait object.
+///
+/// Useful when the locksystem is picked at runtime, or when it is shared
+/// with other parts of a program. It implements `DavLockSystem` itself,
+/// so it can be passed to `DavConfig::locksystem_dyn`.
+pub type DynLockSystem = Arc<dyn DavLockSystem + Send + Sync>;
+
+impl DavLockSystem for DynLockSystem {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>
+    {
+        (**self).lock(path, principal, owner, timeout, scope, depth, submitted_tokens)
+    }
+
+    fn unlock(&self, path: &DavPath, principal: Option<&str>, token: &str) -> Result<(), UnlockError> {
+        (**self).unlock(path, principal, token)
+    }
+
+    fn refresh(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, RefreshError>
+    {
+        (**self).refresh(path, principal, token, timeout)
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        (**self).check(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn check_all(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), Vec<DavLock>>
+    {
+        (**self).check_all(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn check_many(
+        &self,
+        paths: &[(&DavPath, bool)],
+        principal: Option<&str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        (**self).check_many(paths, principal, ignore_principal, submitted_tokens)
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        (**self).discover(path)
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        (**self).delete(path)
+    }
+
+    fn has_token(&self, token: &str, path: &DavPath) -> bool {
+        (**self).has_token(token, path)
+    }
+
+    fn supported_lock(&self) -> Vec<SupportedLockEntry> {
+        (**self).supported_lock()
+    }
+}
+
+// A boxed locksystem is a locksystem too, so that it can be used
+// where a DavLockSystem or DavLockSystemAsync is expected.
+impl DavLockSystem for Box<dyn DavLockSystem> {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>
+    {
+        (**self).lock(path, principal, owner, timeout, scope, depth, submitted_tokens)
+    }
+
+    fn unlock(&self, path: &DavPath, principal: Option<&str>, token: &str) -> Result<(), UnlockError> {
+        (**self).unlock(path, principal, token)
+    }
+
+    fn refresh(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, RefreshError>
+    {
+        (**self).refresh(path, principal, token, timeout)
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        (**self).check(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn check_all(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), Vec<DavLock>>
+    {
+        (**self).check_all(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn check_many(
+        &self,
+        paths: &[(&DavPath, bool)],
+        principal: Option<&str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        (**self).check_many(paths, principal, ignore_principal, submitted_tokens)
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        (**self).discover(path)
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        (**self).delete(path)
+    }
+
+    fn has_token(&self, token: &str, path: &DavPath) -> bool {
+        (**self).has_token(token, path)
+    }
+
+    fn supported_lock(&self) -> Vec<SupportedLockEntry> {
+        (**self).supported_lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LockDepth::Zero;
+    use LockScope::Exclusive;
+
+    #[test]
+    fn remaining() {
+        let path = DavPath::new("/a").unwrap();
+        let now = SystemTime::now();
+        let lock = DavLock::builder(&path)
+            .timeout(Some(Duration::from_secs(60)))
+            .created_at(now)
+            .build();
+        assert_eq!(lock.remaining(now), Some(Duration::from_secs(60)));
+        assert_eq!(
+            lock.remaining(now + Duration::from_secs(45)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            lock.remaining(now + Duration::from_secs(90)),
+            Some(Duration::new(0, 0))
+        );
+        let lock = DavLock::builder(&path).build();
+        assert_eq!(lock.remaining(now), None);
+    }
+
+    #[test]
+    fn timeout_at() {
+        let path = DavPath::new("/a").unwrap();
+        let now = SystemTime::now();
+        let deadline = now + Duration::new(30, 500);
+        let lock = DavLock::builder(&path).timeout_at(deadline).created_at(now).build();
+        assert_eq!(lock.timeout_at, Some(deadline));
+        assert_eq!(lock.timeout, Some(Duration::new(30, 500)));
+        let lock = DavLock::builder(&path)
+            .timeout_at(deadline)
+            .timeout(Some(Duration::from_secs(10)))
+            .created_at(now)
+            .build();
+        assert_eq!(lock.timeout_at, Some(now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn applies_to() {
+        let p = |s: &str| DavPath::new(s).unwrap();
+        let lock = DavLock::builder(&p("/a/b/")).build();
+        assert!(lock.applies_to(&p("/a/b")));
+        assert!(lock.applies_to(&p("/a/b/")));
+        assert!(!lock.applies_to(&p("/a/b/c")));
+        assert!(!lock.applies_to(&p("/a")));
+        assert!(!lock.applies_to(&p("/a/bc")));
+        let lock = DavLock::builder(&p("/a/b/")).deep(true).build();
+        assert!(lock.applies_to(&p("/a/b")));
+        assert!(lock.applies_to(&p("/a/b/c/d")));
+        assert!(!lock.applies_to(&p("/a")));
+        assert!(!lock.applies_to(&p("/a/bc")));
+        let lock = DavLock::builder(&p("/")).deep(true).build();
+        assert!(lock.applies_to(&p("/x")));
+    }
+
+    #[test]
+    fn scope_and_depth() {
+        let path = DavPath::new("/a").unwrap();
+        let lock = DavLock::builder(&path).build();
+        assert_eq!((lock.scope, lock.depth), (Exclusive, Zero));
+        let lock = DavLock::builder(&path).shared(true).deep(true).build();
+        assert_eq!((lock.scope, lock.depth), (LockScope::Shared, LockDepth::Infinity));
+        assert!(lock.is_shared() && lock.is_deep());
+        assert_eq!(LockScope::from_shared(false), Exclusive);
+        assert_eq!(LockDepth::from_deep(false), Zero);
+    }
+
+    #[test]
+    fn dyn_lock_system() {
+        let path = DavPath::new("/a").unwrap();
+        let ls: DynLockSystem = crate::memls::MemLs::builder().build_dyn();
+        let boxed: Box<dyn DavLockSystem> = Box::new(ls.clone());
+        let lock = boxed
+            .lock(&path, None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.discover(&path), vec![lock.clone()]);
+        assert!(boxed.clone().unlock(&path, None, &lock.token).is_ok());
+        assert!(ls.discover(&path).is_empty());
+    }
+
+    #[test]
+    fn lock_token() {
+        let t = LockToken::parse("urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6").unwrap();
+        assert_eq!(t, "urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6");
+        assert!(t.starts_with("urn:uuid:"));
+        assert!(LockToken::parse("opaquelocktoken:abc").is_ok());
+        assert!(LockToken::parse("").is_err());
+        assert!(LockToken::parse("abc").is_err());
+        assert!(LockToken::parse("urn:").is_err());
+        assert!(LockToken::parse(":abc").is_err());
+        assert!(LockToken::parse("1urn:abc").is_err());
+        assert!(LockToken::parse("<urn:abc>").is_err());
+        assert!(LockToken::parse("urn:a b").is_err());
+        assert!(LockToken::parse(&LockToken::generate()).is_ok());
+    }
+
+    #[test]
+    fn lock_token_header() {
+        let path = DavPath::new("/a").unwrap();
+        let lock = DavLock::builder(&path).token("urn:uuid:abc").build();
+        assert_eq!(lock.lock_token_header(), "<urn:uuid:abc>");
+        let token = parse_lock_token_header(&lock.lock_token_header());
+        assert_eq!(token.as_deref(), Some("urn:uuid:abc"));
+        assert_eq!(parse_lock_token_header(" <urn:uuid:abc> ").as_deref(), Some("urn:uuid:abc"));
+        assert_eq!(parse_lock_token_header("urn:uuid:abc"), None);
+        assert_eq!(parse_lock_token_header("<urn:uuid:abc"), None);
+        assert_eq!(parse_lock_token_header("<>"), None);
+        assert_eq!(parse_lock_token_header("<<urn:uuid:abc>>"), None);
+    }
+}
@@ -0,0 +1,367 @@
+//! Async version of the `locksystem` backend trait.
+//!
+//! The methods of `DavLockSystem` are synchronous. That is fine for
+//! `MemLs` and `FakeLs`, but a locksystem that stores its locks in
+//! a database or a network service needs to do I/O, and doing that
+//! synchronously would block the executor.
+//!
+//! Such a backend can implement `DavLockSystemAsync` instead, and be
+//! passed to the handler with `DavConfig::locksystem_async`. Every
+//! `DavLockSystem` implements `DavLockSystemAsync` as well, and the
+//! handler only uses the async trait, so it works with all locksystems.
+//!
+//! Note that this trait uses the same method names as `DavLockSystem`.
+//! If you have both traits in scope, you'll have to use the
+//! `DavLockSystemAsync::lock(&ls, ...)` syntax.
+//!
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use xmltree::Element;
+
+use crate::davpath::DavPath;
+use crate::ls::{
+    DavLock, DavLockSystem, LockDepth, LockError, LockScope, RefreshError, SupportedLockEntry, UnlockError,
+};
+
+/// Future returned by the methods of `DavLockSystemAsync`.
+pub type LsFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A shareable async locksystem trait object, as passed to
+/// `DavConfig::locksystem_async`.
+pub type DynLockSystemAsync = Arc<dyn DavLockSystemAsync>;
+
+/// The trait that defines an async locksystem.
+///
+/// See `DavLockSystem` for the semantics of the methods.
+pub trait DavLockSystemAsync: Debug + Sync + Send {
+    /// Lock a node.
+    #[allow(clippy::too_many_arguments)]
+    fn lock<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        owner: Option<&'a Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<DavLock, LockError>>;
+
+    /// Unlock a node.
+    fn unlock<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        token: &'a str,
+    ) -> LsFuture<'a, Result<(), UnlockError>>;
+
+    /// Refresh lock.
+    fn refresh<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        token: &'a str,
+        timeout: Option<Duration>,
+    ) -> LsFuture<'a, Result<DavLock, RefreshError>>;
+
+    /// Check if node is locked and if so, if we own all the locks.
+    fn check<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<(), DavLock>>;
+
+    /// Like `check`, but returns all conflicting locks instead of just one.
+    ///
+    /// The default implementation calls `check`, so it returns at most one lock.
+    fn check_all<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<(), Vec<DavLock>>>
+    {
+        let fut = self.check(path, principal, ignore_principal, deep, submitted_tokens);
+        Box::pin(async move { fut.await.map_err(|l| vec![l]) })
+    }
+
+    /// Check a number of paths at once.
+    ///
+    /// The default implementation calls `check` for every path.
+    fn check_many<'a>(
+        &'a self,
+        paths: &'a [(&'a DavPath, bool)],
+        principal: Option<&'a str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<(), DavLock>>
+    {
+        Box::pin(async move {
+            for &(path, deep) in paths {
+                self.check(path, principal, ignore_principal, deep, submitted_tokens.clone())
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Find and return all locks that cover a given path.
+    fn discover<'a>(&'a self, path: &'a DavPath) -> LsFuture<'a, Vec<DavLock>>;
+
+    /// Delete all locks at this path and below (after MOVE or DELETE)
+    fn delete<'a>(&'a self, path: &'a DavPath) -> LsFuture<'a, Result<(), ()>>;
+
+    /// The kinds of locks this locksystem supports.
+    ///
+    /// This does not do any I/O, so it is not async. The default
+    /// implementation returns exclusive and shared write locks.
+    fn supported_lock(&self) -> Vec<SupportedLockEntry> {
+        vec![
+            SupportedLockEntry { shared: false },
+            SupportedLockEntry { shared: true },
+        ]
+    }
+}
+
+// Every synchronous locksystem is also an async locksystem.
+impl<LS: DavLockSystem + ?Sized> DavLockSystemAsync for LS {
+    fn lock<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        owner: Option<&'a Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<DavLock, LockError>>
+    {
+        let res = DavLockSystem::lock(
+            self,
+            path,
+            principal,
+            owner,
+            timeout,
+            scope,
+            depth,
+            submitted_tokens,
+        );
+        Box::pin(future::ready(res))
+    }
+
+    fn unlock<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        token: &'a str,
+    ) -> LsFuture<'a, Result<(), UnlockError>>
+    {
+        Box::pin(future::ready(DavLockSystem::unlock(self, path, principal, token)))
+    }
+
+    fn refresh<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        token: &'a str,
+        timeout: Option<Duration>,
+    ) -> LsFuture<'a, Result<DavLock, RefreshError>>
+    {
+        let res = DavLockSystem::refresh(self, path, principal, token, timeout);
+        Box::pin(future::ready(res))
+    }
+
+    fn check<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<(), DavLock>>
+    {
+        let res = DavLockSystem::check(self, path, principal, ignore_principal, deep, submitted_tokens);
+        Box::pin(future::ready(res))
+    }
+
+    fn check_all<'a>(
+        &'a self,
+        path: &'a DavPath,
+        principal: Option<&'a str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<(), Vec<DavLock>>>
+    {
+        let res = DavLockSystem::check_all(self, path, principal, ignore_principal, deep, submitted_tokens);
+        Box::pin(future::ready(res))
+    }
+
+    fn check_many<'a>(
+        &'a self,
+        paths: &'a [(&'a DavPath, bool)],
+        principal: Option<&'a str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&'a str>,
+    ) -> LsFuture<'a, Result<(), DavLock>>
+    {
+        let res = DavLockSystem::check_many(self, paths, principal, ignore_principal, submitted_tokens);
+        Box::pin(future::ready(res))
+    }
+
+    fn discover<'a>(&'a self, path: &'a DavPath) -> LsFuture<'a, Vec<DavLock>> {
+        Box::pin(future::ready(DavLockSystem::discover(self, path)))
+    }
+
+    fn delete<'a>(&'a self, path: &'a DavPath) -> LsFuture<'a, Result<(), ()>> {
+        Box::pin(future::ready(DavLockSystem::delete(self, path)))
+    }
+
+    fn supported_lock(&self) -> Vec<SupportedLockEntry> {
+        DavLockSystem::supported_lock(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use http::{Request, StatusCode};
+
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::DavHandler;
+
+    // An async locksystem that yields to the executor before it passes
+    // every call on to a MemLs, and counts the calls.
+    #[derive(Debug)]
+    struct YieldLs {
+        ls:    Box<MemLs>,
+        calls: AtomicUsize,
+    }
+
+    impl YieldLs {
+        fn ready<'a, T: Send + 'a>(&self, res: T) -> LsFuture<'a, T> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                res
+            })
+        }
+    }
+
+    impl DavLockSystemAsync for YieldLs {
+        fn lock<'a>(
+            &'a self,
+            path: &'a DavPath,
+            principal: Option<&'a str>,
+            owner: Option<&'a Element>,
+            timeout: Option<Duration>,
+            scope: LockScope,
+            depth: LockDepth,
+            submitted_tokens: Vec<&'a str>,
+        ) -> LsFuture<'a, Result<DavLock, LockError>>
+        {
+            let tokens = submitted_tokens;
+            let res = DavLockSystem::lock(&*self.ls, path, principal, owner, timeout, scope, depth, tokens);
+            self.ready(res)
+        }
+
+        fn unlock<'a>(
+            &'a self,
+            path: &'a DavPath,
+            principal: Option<&'a str>,
+            token: &'a str,
+        ) -> LsFuture<'a, Result<(), UnlockError>>
+        {
+            self.ready(DavLockSystem::unlock(&*self.ls, path, principal, token))
+        }
+
+        fn refresh<'a>(
+            &'a self,
+            path: &'a DavPath,
+            principal: Option<&'a str>,
+            token: &'a str,
+            timeout: Option<Duration>,
+        ) -> LsFuture<'a, Result<DavLock, RefreshError>>
+        {
+            self.ready(DavLockSystem::refresh(&*self.ls, path, principal, token, timeout))
+        }
+
+        fn check<'a>(
+            &'a self,
+            path: &'a DavPath,
+            principal: Option<&'a str>,
+            ignore_principal: bool,
+            deep: bool,
+            submitted_tokens: Vec<&'a str>,
+        ) -> LsFuture<'a, Result<(), DavLock>>
+        {
+            let ls = &*self.ls;
+            self.ready(DavLockSystem::check(ls, path, principal, ignore_principal, deep, submitted_tokens))
+        }
+
+        fn discover<'a>(&'a self, path: &'a DavPath) -> LsFuture<'a, Vec<DavLock>> {
+            self.ready(DavLockSystem::discover(&*self.ls, path))
+        }
+
+        fn delete<'a>(&'a self, path: &'a DavPath) -> LsFuture<'a, Result<(), ()>> {
+            self.ready(DavLockSystem::delete(&*self.ls, path))
+        }
+    }
+
+    const LOCKINFO: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:lockinfo xmlns:D="DAV:">
+  <D:lockscope><D:exclusive/></D:lockscope>
+  <D:locktype><D:write/></D:locktype>
+</D:lockinfo>"#;
+
+    fn req(method: &str, path: &str, body: &'static str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn handler_uses_async_locksystem() {
+        let ls = Arc::new(YieldLs {
+            ls:    MemLs::new(),
+            calls: AtomicUsize::new(0),
+        });
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem_async(ls.clone())
+            .build_handler();
+
+        let resp = dav.handle(req("LOCK", "/a", LOCKINFO)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let path = DavPath::new("/a").unwrap();
+        let locks = DavLockSystem::discover(&*ls.ls, &path);
+        assert_eq!(locks.len(), 1);
+        let calls = ls.calls.load(Ordering::SeqCst);
+        assert!(calls >= 1);
+
+        // a write without the token is refused, with it, it is fine.
+        let resp = dav.handle(req("PUT", "/a", "data")).await;
+        assert_eq!(resp.status(), StatusCode::LOCKED);
+        assert!(ls.calls.load(Ordering::SeqCst) > calls);
+        let mut put = req("PUT", "/a", "data");
+        let hdr = format!("(<{}>)", locks[0].token);
+        put.headers_mut().insert("If", hdr.parse().unwrap());
+        let resp = dav.handle(put).await;
+        assert!(resp.status().is_success());
+    }
+}
@@ -143,7 +143,7 @@ impl DavFileSystem for MemFs {
                 return Err(FsError::Forbidden);
             }
             let mut v: Vec<Box<dyn DavDirEntry>> = Vec::new();
-            for (name, dnode_id) in tree.get_children(node_id)? {
+            for (name, dnode_id) in tree.get_children_sorted(node_id)? {
                 if let Ok(node) = tree.get_node(dnode_id) {
                     v.push(Box::new(node.as_dirent(&name)));
                 }
@@ -6,388 +6,4160 @@
 //! This means you have to create the instance once, using `MemLs::new`, store
 //! it in your handler struct, and clone() it every time you pass
 //! it to the DavHandler. As a MemLs struct is just a handle, cloning is cheap.
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+//!
+//! If you want to change the defaults, use `MemLs::builder()` to get
+//! a `MemLsConfig`, and call `build()` on it when you're done.
+//!
+//! Expired locks are ignored by `lock`, `check` and `discover`, but they are
+//! only actually removed from memory when `MemLs::sweep` is called, either
+//! by hand or by a sweeper (`MemLs::start_sweeper`, `MemLs::spawn_sweeper_tokio`).
+//!
+//! The root `/` is treated like any other collection. A lock on `/` is
+//! found by `discover` for every path, but for `check` and `lock` it only
+//! covers the members of the root if it is a deep lock.
+//!
+//! A thread that panics while using the locksystem does not make it
+//! unusable for other threads: the poisoned state is simply used as is.
+//! With the `memls-parking-lot` feature, the state is protected by a
+//! `parking_lot::RwLock` instead of the one from `std`. That one has no
+//! poisoning at all, and less overhead under contention.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+#[cfg(feature = "memls-serde")]
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+#[cfg(not(feature = "memls-parking-lot"))]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::time::{Duration, Instant, SystemTime};
 
+use futures::future::{self, AbortHandle};
+#[cfg(feature = "memls-parking-lot")]
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use uuid::Uuid;
-use xmltree::Element;
+use xmltree::{Element, EmitterConfig};
 
 use crate::davpath::DavPath;
-use crate::fs::FsResult;
+use crate::fs::{FsError, FsResult};
 use crate::ls::*;
-use crate::tree;
+use crate::tree::{self, TreeResult};
 
 type Tree = tree::Tree<Vec<u8>, Vec<DavLock>>;
 
+// The lock around the state, from std or parking_lot. The contention
+// counters are kept outside of it, so that a thread that has to wait
+// is counted before it gets in.
+#[derive(Debug)]
+struct StateLock {
+    state:      RwLock<MemLsInner>,
+    contention: Contention,
+}
+
+// Default maximum number of segments in a locked path.
+const DEFAULT_MAX_PATH_DEPTH: usize = 1024;
+
 /// Ephemeral in-memory LockSystem.
+///
+/// Clones share the same state.
 #[derive(Debug, Clone)]
-pub struct MemLs(Arc<Mutex<MemLsInner>>);
+pub struct MemLs(Arc<StateLock>);
 
+/// A lock that is released when the guard is dropped.
+/// Returned by `MemLs::lock_guard`.
+///
+/// The guard only holds a weak reference to the `MemLs`, if that
+/// is gone by the time the guard is dropped, nothing happens.
 #[derive(Debug)]
-struct MemLsInner {
-    tree:  Tree,
-    locks: HashMap<Vec<u8>, u64>,
+pub struct LockGuard {
+    ls:   Weak<StateLock>,
+    lock: DavLock,
 }
 
-impl MemLs {
-    /// Create a new "memls" locksystem.
-    pub fn new() -> Box<MemLs> {
-        let inner = MemLsInner {
-            tree:  Tree::new(Vec::new()),
-            locks: HashMap::new(),
-        };
-        Box::new(MemLs(Arc::new(Mutex::new(inner))))
+impl LockGuard {
+    /// The lock that this guard holds.
+    pub fn lock(&self) -> &DavLock {
+        &self.lock
     }
 }
 
-impl DavLockSystem for MemLs {
-    fn lock(
-        &self,
-        path: &DavPath,
-        principal: Option<&str>,
-        owner: Option<&Element>,
-        timeout: Option<Duration>,
-        shared: bool,
-        deep: bool,
-    ) -> Result<DavLock, DavLock>
-    {
-        let inner = &mut *self.0.lock().unwrap();
-
-        // any locks in the path?
-        let rc = check_locks_to_path(&inner.tree, path, None, true, &Vec::new(), shared);
-        trace!("lock: check_locks_to_path: {:?}", rc);
-        rc?;
-
-        // if it's a deep lock we need to check if there are locks furter along the path.
-        if deep {
-            let rc = check_locks_from_path(&inner.tree, path, None, true, &Vec::new(), shared);
-            trace!("lock: check_locks_from_path: {:?}", rc);
-            rc?;
-        }
-
-        // create lock.
-        let node = get_or_create_path_node(&mut inner.tree, path);
-        let timeout_at = match timeout {
-            None => None,
-            Some(d) => Some(SystemTime::now() + d),
-        };
-        let lock = DavLock {
-            token:      Uuid::new_v4().urn().to_string(),
-            path:       path.clone(),
-            principal:  principal.map(|s| s.to_string()),
-            owner:      owner.cloned(),
-            timeout_at: timeout_at,
-            timeout:    timeout,
-            shared:     shared,
-            deep:       deep,
-        };
-        trace!("lock {} created", &lock.token);
-        let slock = lock.clone();
-        node.push(slock);
-        Ok(lock)
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // by token: the lock might have been moved since.
+        if let Some(inner) = self.ls.upgrade() {
+            MemLs(inner).unlock_by_token(&self.lock.token);
+        }
     }
+}
 
-    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
-        let inner = &mut *self.0.lock().unwrap();
-        let node_id = match lookup_lock(&inner.tree, path, token) {
-            None => {
-                trace!("unlock: {} not found at {}", token, path);
-                return Err(());
-            },
-            Some(n) => n,
-        };
-        let len = {
-            let node = inner.tree.get_node_mut(node_id).unwrap();
-            let idx = node.iter().position(|n| n.token.as_str() == token).unwrap();
-            node.remove(idx);
-            node.len()
-        };
-        if len == 0 {
-            inner.tree.delete_node(node_id).ok();
-        }
-        Ok(())
+/// Statistics returned by `MemLs::stats`.
+///
+/// Expired locks that have not been swept yet are not counted.
+#[derive(Debug, Clone, Default)]
+pub struct MemLsStats {
+    /// Total number of locks.
+    pub locks:     usize,
+    /// Number of shared locks.
+    pub shared:    usize,
+    /// Number of exclusive locks.
+    pub exclusive: usize,
+    /// Number of deep (Depth: infinity) locks.
+    pub deep:      usize,
+    /// Number of nodes in the lock tree, including the root.
+    pub nodes:     usize,
+}
+
+/// Contention counters of the internal state lock of a `MemLs`,
+/// returned by `MemLs::contention_stats`.
+#[derive(Debug, Clone, Default)]
+pub struct ContentionStats {
+    /// Number of times the state was locked.
+    pub acquisitions: u64,
+    /// How many of those had to wait for another thread.
+    pub contended:    u64,
+    /// Total time spent waiting.
+    pub wait_time:    Duration,
+}
+
+/// A human-readable copy of a lock, returned by `MemLs::dump`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "memls-serde", derive(serde::Serialize))]
+pub struct DavLockSnapshot {
+    /// Path of the lock root, URL encoded, with prefix.
+    pub path:              String,
+    /// Token.
+    pub token:             String,
+    /// Shared.
+    pub shared:            bool,
+    /// Deep.
+    pub deep:              bool,
+    /// Principal.
+    pub principal:         Option<String>,
+    /// Owner, serialized as XML.
+    pub owner:             Option<String>,
+    /// When the lock was created.
+    pub created_at:        SystemTime,
+    /// When the lock turns stale. `None` means never.
+    pub timeout_at:        Option<SystemTime>,
+    /// When the lock was last refreshed.
+    pub last_refreshed_at: SystemTime,
+    /// Extra data attached to the lock.
+    pub extra:             HashMap<String, String>,
+    /// The lock has expired, but has not been swept yet.
+    pub expired:           bool,
+}
+
+/// Where a lock returned by `MemLs::discover_detailed` comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockOrigin {
+    /// The lock is rooted at the path itself.
+    Direct,
+    /// The lock is a deep lock on an ancestor, this many levels up.
+    Inherited(usize),
+}
+
+/// Error returned by `MemLs::check_tokens`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckError {
+    /// The path is locked by this lock, which the client does not hold (423).
+    Locked(Box<DavLock>),
+    /// This submitted token is not a valid lock token (400).
+    MalformedToken(String),
+}
+
+/// How `refresh` calculates the new timeout of a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshPolicy {
+    /// The lock expires the requested timeout from now. This is the default.
+    ReplaceFromNow,
+    /// The requested timeout is added to the current expiry time of the lock.
+    ExtendFromCurrentExpiry,
+    /// Like `ExtendFromCurrentExpiry`, but the lock never expires more than
+    /// `max_timeout` from now. Without `max_timeout` this is the same
+    /// as `ExtendFromCurrentExpiry`.
+    CapAtMax,
+}
+
+/// Events passed to the callback that is set with `MemLsConfig::on_event`.
+#[derive(Debug, Clone)]
+pub enum LockEvent {
+    /// A lock was created.
+    Acquired(DavLock),
+    /// A lock was refreshed.
+    Refreshed(DavLock),
+    /// A lock was removed by `unlock`, `unlock_by_token` or
+    /// `unlock_all_by_principal`.
+    Released {
+        /// Token of the lock.
+        token: String,
+        /// Path of the lock.
+        path:  DavPath,
+    },
+    /// An expired lock was removed by `sweep`.
+    Expired(DavLock),
+}
+
+/// Source of the current time for a `MemLs`. The default is `SystemClock`.
+///
+/// With a clock that is advanced by hand, lock expiry can be tested
+/// without waiting for it.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// A `Clock` that returns the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
     }
+}
 
-    fn refresh(&self, path: &DavPath, token: &str, timeout: Option<Duration>) -> Result<DavLock, ()> {
-        trace!("refresh lock {}", token);
-        let inner = &mut *self.0.lock().unwrap();
-        let node_id = match lookup_lock(&inner.tree, path, token) {
-            None => {
-                trace!("lock not found");
-                return Err(());
-            },
-            Some(n) => n,
-        };
-        let node = (&mut inner.tree).get_node_mut(node_id).unwrap();
-        let idx = node.iter().position(|n| n.token.as_str() == token).unwrap();
-        let lock = &mut node[idx];
-        let timeout_at = match timeout {
-            None => None,
-            Some(d) => Some(SystemTime::now() + d),
-        };
-        lock.timeout = timeout;
-        lock.timeout_at = timeout_at;
-        Ok(lock.clone())
+// Generates lock tokens.
+type TokenGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+
+// Called on lock events.
+type EventCallback = Arc<dyn Fn(LockEvent) + Send + Sync>;
+
+/// Configuration of a MemLs locksystem.
+#[derive(Clone, Default)]
+pub struct MemLsConfig {
+    // Upper bound for lock timeouts. `None` means no limit.
+    max_timeout:             Option<Duration>,
+    // Maximum number of locks on one node. `None` means no limit.
+    max_locks_per_node:      Option<usize>,
+    // Match paths case-insensitively (ASCII only).
+    case_insensitive:        bool,
+    // Maximum number of segments in a locked path.
+    max_path_depth:          Option<usize>,
+    // Custom lock token generator. `None` means UUIDs.
+    token_generator:         Option<TokenGenerator>,
+    // How to refresh locks. `None` means ReplaceFromNow.
+    refresh_policy:          Option<RefreshPolicy>,
+    // Only the principal that created a lock may unlock or refresh it.
+    enforce_principal:       bool,
+    // Callback for lock events.
+    on_event:                Option<EventCallback>,
+    // Refuse locks without a timeout.
+    forbid_infinite_timeout: bool,
+    // Source of the current time. `None` means SystemClock.
+    clock:                   Option<Arc<dyn Clock>>,
+    // Maximum number of locks in the whole locksystem. `None` means no limit.
+    max_total_locks:         Option<usize>,
+    // Maximum size of the owner element, serialized as XML. `None` means no limit.
+    max_owner_size:          Option<usize>,
+    // check_tokens rejects submitted tokens that are not valid lock tokens.
+    strict_tokens:           bool,
+}
+
+impl std::fmt::Debug for MemLsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MemLsConfig")
+            .field("max_timeout", &self.max_timeout)
+            .field("max_locks_per_node", &self.max_locks_per_node)
+            .field("case_insensitive", &self.case_insensitive)
+            .field("max_path_depth", &self.max_path_depth)
+            .field("token_generator", &self.token_generator.as_ref().map(|_| "Fn"))
+            .field("refresh_policy", &self.refresh_policy)
+            .field("enforce_principal", &self.enforce_principal)
+            .field("on_event", &self.on_event.as_ref().map(|_| "Fn"))
+            .field("forbid_infinite_timeout", &self.forbid_infinite_timeout)
+            .field("clock", &self.clock.as_ref().map(|_| "Clock"))
+            .field("max_total_locks", &self.max_total_locks)
+            .field("max_owner_size", &self.max_owner_size)
+            .field("strict_tokens", &self.strict_tokens)
+            .finish()
     }
+}
 
-    fn check(
-        &self,
-        path: &DavPath,
-        principal: Option<&str>,
-        ignore_principal: bool,
-        deep: bool,
-        submitted_tokens: Vec<&str>,
-    ) -> Result<(), DavLock>
-    {
-        let inner = &*self.0.lock().unwrap();
-        let _st = submitted_tokens.clone();
-        let rc = check_locks_to_path(
-            &inner.tree,
-            path,
-            principal,
-            ignore_principal,
-            &submitted_tokens,
-            false,
-        );
-        trace!("check: check_lock_to_path: {:?}: {:?}", _st, rc);
-        rc?;
+// Counters for MemLs::contention_stats.
+#[derive(Debug, Default)]
+struct Contention {
+    acquisitions: AtomicU64,
+    contended:    AtomicU64,
+    wait_nanos:   AtomicU64,
+}
 
-        // if it's a deep lock we need to check if there are locks furter along the path.
-        if deep {
-            let rc = check_locks_from_path(
-                &inner.tree,
-                path,
-                principal,
-                ignore_principal,
-                &submitted_tokens,
-                false,
-            );
-            trace!("check: check_locks_from_path: {:?}", rc);
-            rc?;
+impl Contention {
+    // Called when the state is locked by someone else, before waiting.
+    fn contended(&self) {
+        self.contended.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record(&self, waited: Option<Duration>) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(waited) = waited {
+            let nanos = u64::try_from(waited.as_nanos()).unwrap_or(u64::MAX);
+            self.wait_nanos.fetch_add(nanos, Ordering::Relaxed);
         }
-        Ok(())
     }
+}
 
-    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
-        let inner = &*self.0.lock().unwrap();
-        list_locks(&inner.tree, path)
+// When a new or refreshed lock expires: after a timeout (`None` is
+// infinite), or at a fixed time.
+#[derive(Debug, Clone, Copy)]
+enum Expiry {
+    After(Option<Duration>),
+    At(SystemTime),
+}
+
+#[derive(Debug)]
+struct MemLsInner {
+    tree:       Tree,
+    // The node of every lock in the tree, including expired ones, by token.
+    tokens:     HashMap<LockToken, u64>,
+    config:     MemLsConfig,
+    // Tokens of the locks on lock-null resources, see MemLs::lock_null.
+    lock_null:  HashSet<LockToken>,
+}
+
+impl MemLsInner {
+    // Number of locks in the tree, including expired ones.
+    fn lock_count(&self) -> usize {
+        self.tokens.len()
     }
 
-    fn delete(&self, path: &DavPath) -> Result<(), ()> {
-        let inner = &mut *self.0.lock().unwrap();
-        if let Some(node_id) = lookup_node(&inner.tree, path) {
-            (&mut inner.tree).delete_subtree(node_id).ok();
+    // Remove the tokens of locks that were removed from the tree from
+    // the index, and from the lock-null set.
+    fn forget(&mut self, locks: &[DavLock]) {
+        for lock in locks {
+            self.tokens.remove(&lock.token);
+            self.lock_null.remove(&lock.token);
         }
-        Ok(())
     }
-}
 
-// check if there are any locks along the path.
-fn check_locks_to_path(
-    tree: &Tree,
-    path: &DavPath,
-    principal: Option<&str>,
-    ignore_principal: bool,
-    submitted_tokens: &Vec<&str>,
-    shared_ok: bool,
-) -> Result<(), DavLock>
-{
-    // path segments
-    let segs = path_to_segs(path, true);
-    let last_seg = segs.len() - 1;
+    // Delete a subtree of the lock tree, and keep the token index up to date.
+    fn delete_subtree(&mut self, node_id: u64) -> Vec<DavLock> {
+        let locks = subtree_locks(&self.tree, node_id);
+        self.forget(&locks);
+        self.tree.delete_subtree(node_id).ok();
+        locks
+    }
 
-    // state
-    let mut holds_lock = false;
-    let mut first_lock_seen: Option<&DavLock> = None;
+    // Find the node of the lock with this token, if the lock is on
+    // "path" or on one of its parents.
+    fn lookup_lock(&self, path: &DavPath, token: &str) -> Option<u64> {
+        let node_id = *self.tokens.get(token)?;
+        let node = self.tree.get_node(node_id).ok()?;
+        let lock = node.iter().find(|l| l.token == token)?;
+        let lock_root = self.config.tree_path(&lock.path);
+        if path_to_segs(path, false).starts_with(&path_to_segs(&lock_root, false)) {
+            Some(node_id)
+        } else {
+            trace!("lookup_lock: {} is at {}, not on {}", token, lock.path, path);
+            None
+        }
+    }
 
-    // walk over path segments starting at root.
-    let mut node_id = tree::ROOT_ID;
-    for (i, seg) in segs.into_iter().enumerate() {
-        node_id = match get_child(tree, node_id, seg) {
-            Ok(n) => n,
-            Err(_) => break,
+    // The body of MemLs::relocate and MemLs::on_move. Before the move, the
+    // locks at `from` and below for which "remove" returns true are removed,
+    // as are all locks at `to` and below. Returns those locks.
+    fn relocate<F>(&mut self, from: &DavPath, to: &DavPath, remove: F) -> FsResult<Vec<DavLock>>
+    where F: Fn(&DavLock) -> bool {
+        let key_from = self.config.tree_path(from);
+        let key_to = self.config.tree_path(to);
+        let segs_from = path_to_segs(&key_from, false);
+        let mut segs_to = path_to_segs(&key_to, false);
+        if !relocatable(&segs_from, &segs_to) {
+            return Err(FsError::Forbidden);
+        }
+        let node_id = match lookup_node(&self.tree, &key_from) {
+            Some(n) => n,
+            None => {
+                // nothing to move, the destination is replaced all the same.
+                let dest_id = match lookup_node(&self.tree, &key_to) {
+                    Some(n) => n,
+                    None => return Ok(Vec::new()),
+                };
+                let parent_id = self.tree.get_parent(dest_id)?;
+                let removed = self.delete_subtree(dest_id);
+                remove_empty_nodes(&mut self.tree, parent_id);
+                return Ok(removed);
+            },
         };
-        let node_locks = match tree.get_node(node_id) {
-            Ok(n) => n,
-            Err(_) => break,
+
+        // everything that can fail is done before any lock is touched:
+        // find or create the new parent.
+        let from_parent_id = self.tree.get_parent(node_id)?;
+        let name = segs_to.pop().unwrap().to_vec();
+        let parent_id = get_or_create_nodes(&mut self.tree, &segs_to)?;
+
+        // replace the destination.
+        let mut removed = match self.tree.get_child(parent_id, &name) {
+            Ok(dest_id) => self.delete_subtree(dest_id),
+            Err(_) => Vec::new(),
         };
 
-        for nl in node_locks {
-            if i < last_seg && !nl.deep {
-                continue;
-            }
-            if submitted_tokens.iter().any(|t| &nl.token == t) &&
-                (ignore_principal || principal == nl.principal.as_ref().map(|p| p.as_str()))
-            {
-                // fine, we hold this lock.
-                holds_lock = true;
-            } else {
-                // exclusive locks are fatal.
-                if !nl.shared {
-                    return Err(nl.to_owned());
-                }
-                // remember first shared lock seen.
-                if !shared_ok {
-                    first_lock_seen.get_or_insert(nl);
+        // get rid of the locks that do not move along.
+        let pruned = prune_node(&mut self.tree, node_id, &remove);
+        self.forget(&pruned);
+        removed.extend(pruned);
+        if self.tree.get_node(node_id).is_err() {
+            // nothing left to move.
+            remove_empty_nodes(&mut self.tree, parent_id);
+            remove_empty_nodes(&mut self.tree, from_parent_id);
+            return Ok(removed);
+        }
+
+        // and move the node there.
+        self.tree.move_subtree(node_id, parent_id, name)?;
+        remove_empty_nodes(&mut self.tree, from_parent_id);
+
+        // and update the paths of the locks.
+        let skip = segs_from.len();
+        let mut stack = vec![node_id];
+        while let Some(id) = stack.pop() {
+            if let Ok(node) = self.tree.get_node_mut(id) {
+                for lock in node.iter_mut() {
+                    lock.path = rebase_path(&lock.path, to, skip);
                 }
             }
+            if let Ok(children) = self.tree.get_children(id) {
+                stack.extend(children.map(|(_, child_id)| child_id));
+            }
         }
+        Ok(removed)
     }
+}
 
-    // return conflicting lock on error.
-    if !holds_lock && first_lock_seen.is_some() {
-        return Err(first_lock_seen.unwrap().to_owned());
+impl MemLsConfig {
+    /// Create a new configuration builder.
+    pub fn new() -> MemLsConfig {
+        MemLsConfig::default()
     }
 
-    Ok(())
-}
+    /// Use the configuration that was built to generate a MemLs.
+    pub fn build(self) -> Box<MemLs> {
+        let inner = MemLsInner {
+            tree:      Tree::new(Vec::new()),
+            tokens:    HashMap::new(),
+            config:    self,
+            lock_null: HashSet::new(),
+        };
+        Box::new(MemLs(Arc::new(StateLock {
+            state:      RwLock::new(inner),
+            contention: Contention::default(),
+        })))
+    }
 
-// See if there are locks in any path below this collection.
-fn check_locks_from_path(
-    tree: &Tree,
-    path: &DavPath,
-    principal: Option<&str>,
-    ignore_principal: bool,
-    submitted_tokens: &Vec<&str>,
-    shared_ok: bool,
-) -> Result<(), DavLock>
-{
-    let node_id = match lookup_node(tree, path) {
-        Some(id) => id,
-        None => return Ok(()),
-    };
-    check_locks_from_node(
-        tree,
-        node_id,
-        principal,
-        ignore_principal,
-        submitted_tokens,
-        shared_ok,
-    )
-}
+    /// Like `build`, but returns the MemLs as a `DynLockSystem`.
+    pub fn build_dyn(self) -> DynLockSystem {
+        Arc::new(*self.build())
+    }
 
-// See if there are locks in any nodes below this node.
-fn check_locks_from_node(
-    tree: &Tree,
-    node_id: u64,
-    principal: Option<&str>,
-    ignore_principal: bool,
-    submitted_tokens: &Vec<&str>,
-    shared_ok: bool,
-) -> Result<(), DavLock>
-{
-    let node_locks = match tree.get_node(node_id) {
-        Ok(n) => n,
-        Err(_) => return Ok(()),
-    };
-    for nl in node_locks {
-        if !nl.shared || !shared_ok {
-            if !submitted_tokens.iter().any(|t| t == &nl.token) ||
-                (!ignore_principal && principal != nl.principal.as_ref().map(|p| p.as_str()))
-            {
-                return Err(nl.to_owned());
-            }
-        }
+    /// Maximum timeout of a lock. Longer timeouts are clamped to this
+    /// value, and locks that are requested without a timeout (infinite)
+    /// get this timeout instead.
+    pub fn max_timeout(self, timeout: Duration) -> Self {
+        let mut this = self;
+        this.max_timeout = Some(timeout);
+        this
     }
-    if let Ok(children) = tree.get_children(node_id) {
-        for (_, node_id) in children {
-            if let Err(l) = check_locks_from_node(
-                tree,
-                node_id,
-                principal,
-                ignore_principal,
-                submitted_tokens,
-                shared_ok,
-            ) {
-                return Err(l);
-            }
-        }
+
+    /// Maximum number of locks that a single resource can hold.
+    /// Only relevant for shared locks. The default is unlimited.
+    pub fn max_locks_per_node(self, max: usize) -> Self {
+        let mut this = self;
+        this.max_locks_per_node = Some(max);
+        this
     }
-    Ok(())
-}
 
-// Find or create node.
-fn get_or_create_path_node<'a>(tree: &'a mut Tree, path: &DavPath) -> &'a mut Vec<DavLock> {
-    let mut node_id = tree::ROOT_ID;
-    for seg in path_to_segs(path, false) {
-        node_id = match tree.get_child(node_id, seg) {
-            Ok(n) => n,
-            Err(_) => tree.add_child(node_id, seg.to_vec(), Vec::new(), false).unwrap(),
-        };
+    /// Maximum number of locks in the whole locksystem, to bound the
+    /// memory it uses. When it is reached, `lock` fails with
+    /// `LockError::StorageFull`. Expired locks count until they are
+    /// removed by `MemLs::sweep`. The default is unlimited.
+    pub fn max_total_locks(self, max: usize) -> Self {
+        let mut this = self;
+        this.max_total_locks = Some(max);
+        this
     }
-    tree.get_node_mut(node_id).unwrap()
-}
 
-// Find lock in path.
-fn lookup_lock(tree: &Tree, path: &DavPath, token: &str) -> Option<u64> {
-    trace!("lookup_lock: {}", token);
+    /// Maximum size in bytes of the `owner` element of a lock, serialized
+    /// as XML. A LOCK request with a larger owner fails with
+    /// `LockError::OwnerTooLarge`. The default is unlimited, but a public
+    /// server should set this, as the owner is stored with every lock.
+    pub fn max_owner_size(self, max: usize) -> Self {
+        let mut this = self;
+        this.max_owner_size = Some(max);
+        this
+    }
 
-    let mut node_id = tree::ROOT_ID;
-    for seg in path_to_segs(path, true) {
-        trace!(
-            "lookup_lock: node {} seg {}",
-            node_id,
-            String::from_utf8_lossy(seg)
-        );
-        node_id = match get_child(tree, node_id, seg) {
-            Ok(n) => n,
-            Err(_) => break,
-        };
-        let node = tree.get_node(node_id).unwrap();
-        trace!("lookup_lock: locks here: {:?}", &node);
-        if node.iter().any(|n| n.token == token) {
-            return Some(node_id);
-        }
+    /// Match paths case-insensitively, so that a lock on `/Foo` also
+    /// covers `/foo`. Useful for Windows clients. The default is `false`.
+    ///
+    /// Case folding is ASCII-only: `A-Z` match `a-z`, all other
+    /// bytes must match exactly. The lock itself keeps the path as it
+    /// was given in the LOCK request.
+    pub fn case_insensitive(self, on: bool) -> Self {
+        let mut this = self;
+        this.case_insensitive = on;
+        this
     }
-    trace!("lookup_lock: fail");
-    None
-}
 
-// Find node ID for path.
-fn lookup_node(tree: &Tree, path: &DavPath) -> Option<u64> {
-    let mut node_id = tree::ROOT_ID;
-    for seg in path_to_segs(path, false) {
-        node_id = match tree.get_child(node_id, seg) {
-            Ok(n) => n,
-            Err(_) => return None,
-        };
+    /// Maximum number of segments in the path of a lock. Every segment
+    /// is a node in the lock tree, so this bounds the depth of the tree.
+    /// The default is 1024.
+    pub fn max_path_depth(self, depth: usize) -> Self {
+        let mut this = self;
+        this.max_path_depth = Some(depth);
+        this
     }
-    Some(node_id)
-}
 
-// Find all locks in a path
-fn list_locks(tree: &Tree, path: &DavPath) -> Vec<DavLock> {
-    let mut locks = Vec::new();
+    /// Use a custom function to generate lock tokens, instead of the
+    /// default `urn:uuid:<uuid>`. The tokens must be unique, and valid
+    /// according to `LockToken::parse`.
+    ///
+    /// RFC4918 requires lock tokens to be absolute URIs, so if you
+    /// do not use UUIDs, use something like `opaquelocktoken:<id>`.
+    pub fn token_generator<F>(self, generator: F) -> Self
+    where F: Fn() -> String + Send + Sync + 'static {
+        let mut this = self;
+        this.token_generator = Some(Arc::new(generator));
+        this
+    }
 
-    let mut node_id = tree::ROOT_ID;
-    if let Ok(node) = tree.get_node(node_id) {
-        locks.extend_from_slice(node);
+    /// Generate lock tokens from a counter instead of random UUIDs:
+    /// `urn:uuid:00000000-0000-0000-0000-000000000001`, `...0002`, etc.
+    ///
+    /// The tokens are predictable, so only use this in tests. Every
+    /// call of this method starts a new counter.
+    pub fn counter_tokens(self) -> Self {
+        let counter = AtomicU64::new(0);
+        self.token_generator(move || {
+            let n = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            Uuid::from_u128(n as u128).urn().to_string()
+        })
     }
-    for seg in path_to_segs(path, false) {
-        node_id = match tree.get_child(node_id, seg) {
-            Ok(n) => n,
-            Err(_) => break,
-        };
-        if let Ok(node) = tree.get_node(node_id) {
-            locks.extend_from_slice(node);
-        }
+
+    /// Set the way a lock refresh calculates the new timeout.
+    /// The default is `RefreshPolicy::ReplaceFromNow`.
+    ///
+    /// With the other policies, a refresh that asks for an infinite
+    /// timeout keeps the timeout that the lock already had.
+    pub fn refresh_policy(self, policy: RefreshPolicy) -> Self {
+        let mut this = self;
+        this.refresh_policy = Some(policy);
+        this
     }
-    locks
-}
 
-fn path_to_segs(path: &DavPath, include_root: bool) -> Vec<&[u8]> {
-    let path = path.as_bytes();
-    let mut segs: Vec<&[u8]> = path.split(|&c| c == b'/').filter(|s| s.len() > 0).collect();
-    if include_root {
-        segs.insert(0, b"");
+    /// Only allow the principal that created a lock to unlock or refresh it.
+    /// An unlock by someone else fails with `UnlockError::PrincipalMismatch`.
+    /// The default is `false`, anyone who has the lock token can unlock.
+    pub fn enforce_principal(self, on: bool) -> Self {
+        let mut this = self;
+        this.enforce_principal = on;
+        this
     }
-    segs
-}
 
-fn get_child(tree: &Tree, node_id: u64, seg: &[u8]) -> FsResult<u64> {
-    if seg.len() == 0 {
-        return Ok(node_id);
+    /// Validate the submitted lock tokens in `MemLs::check_tokens`. A
+    /// token that is not a valid lock token (see `LockToken::parse`), such
+    /// as a bare word, makes it fail with `CheckError::MalformedToken`, so
+    /// that the request can be answered with 400 instead of 423.
+    /// The default is `false`: such tokens never match a lock, and are
+    /// otherwise ignored, like `check` always does.
+    pub fn strict_tokens(self, on: bool) -> Self {
+        let mut this = self;
+        this.strict_tokens = on;
+        this
+    }
+
+    /// Call `callback` when a lock is acquired, refreshed, released,
+    /// or removed by `sweep` because it expired.
+    ///
+    /// The callback is called after the internal lock of the MemLs has been
+    /// released, so it can call back into the MemLs without deadlocking.
+    pub fn on_event<F>(self, callback: F) -> Self
+    where F: Fn(LockEvent) + Send + Sync + 'static {
+        let mut this = self;
+        this.on_event = Some(Arc::new(callback));
+        this
+    }
+
+    /// Refuse to create locks with an infinite timeout. `lock` then
+    /// fails with `LockError::InfiniteTimeout`, and a `refresh` without
+    /// a timeout keeps the timeout the lock already had.
+    ///
+    /// If `max_timeout` is also set, an infinite timeout is changed
+    /// into `max_timeout` instead, so this setting has no effect.
+    pub fn forbid_infinite_timeout(self, on: bool) -> Self {
+        let mut this = self;
+        this.forbid_infinite_timeout = on;
+        this
+    }
+
+    /// Use `clock` as the source of the current time.
+    pub fn clock<C: Clock + 'static>(self, clock: C) -> Self {
+        let mut this = self;
+        this.clock = Some(Arc::new(clock));
+        this
+    }
+
+    // The current time, according to the clock.
+    fn now(&self) -> SystemTime {
+        match self.clock {
+            Some(ref clock) => clock.now(),
+            None => SystemTime::now(),
+        }
+    }
+
+    // Calculate the new timeout and expiry time of a lock that is refreshed.
+    fn refresh_timeout(
+        &self,
+        lock: &DavLock,
+        timeout: Option<Duration>,
+        now: SystemTime,
+    ) -> (Option<Duration>, Option<SystemTime>)
+    {
+        let timeout = match timeout {
+            None if self.forbid_infinite_timeout => lock.timeout,
+            t => t,
+        };
+        let policy = self.refresh_policy.unwrap_or(RefreshPolicy::ReplaceFromNow);
+        if policy == RefreshPolicy::ReplaceFromNow {
+            let timeout = self.clamp_timeout(timeout);
+            return (timeout, timeout.and_then(|d| now.checked_add(d)));
+        }
+        let timeout = match self.clamp_timeout(timeout.or(lock.timeout)) {
+            Some(d) => d,
+            None => return (None, None),
+        };
+        let base = match lock.timeout_at {
+            Some(t) if t > now => t,
+            _ => now,
+        };
+        // if the expiry time overflows, the lock never expires.
+        let mut timeout_at = base.checked_add(timeout);
+        if let (RefreshPolicy::CapAtMax, Some(max)) = (policy, self.max_timeout) {
+            if let Some(cap) = now.checked_add(max) {
+                timeout_at = Some(timeout_at.map_or(cap, |t| cmp::min(t, cap)));
+            }
+        }
+        (Some(timeout), timeout_at)
+    }
+
+    // Generate a new lock token.
+    // Fails if the token generator returns something that is not a valid token.
+    fn new_token(&self) -> Result<LockToken, InvalidLockToken> {
+        match self.token_generator {
+            Some(ref generator) => LockToken::parse(&generator()),
+            None => Ok(LockToken::generate()),
+        }
+    }
+
+    // The path as it is used as key in the lock tree.
+    fn tree_path<'a>(&self, path: &'a DavPath) -> Cow<'a, DavPath> {
+        if self.case_insensitive {
+            Cow::Owned(path.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(path)
+        }
+    }
+
+    // Apply the max_timeout limit to a requested expiry time.
+    fn clamp_deadline(&self, deadline: SystemTime, now: SystemTime) -> SystemTime {
+        match self.max_timeout.and_then(|max| now.checked_add(max)) {
+            Some(cap) => cmp::min(deadline, cap),
+            None => deadline,
+        }
+    }
+
+    // Apply the max_timeout limit to a requested timeout.
+    fn clamp_timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match (timeout, self.max_timeout) {
+            (Some(t), Some(max)) => Some(cmp::min(t, max)),
+            (None, Some(max)) => Some(max),
+            (t, None) => t,
+        }
+    }
+}
+
+impl Default for MemLs {
+    /// Same as `MemLs::new`, but not boxed.
+    fn default() -> MemLs {
+        *MemLs::new()
+    }
+}
+
+impl MemLs {
+    /// Create a new "memls" locksystem.
+    pub fn new() -> Box<MemLs> {
+        MemLsConfig::new().build()
+    }
+
+    /// Create a new "memls" locksystem that uses `clock` to tell the time.
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> Box<MemLs> {
+        MemLsConfig::new().clock(clock).build()
+    }
+
+    /// Create a new configuration builder.
+    pub fn builder() -> MemLsConfig {
+        MemLsConfig::new()
+    }
+
+    /// Create a new "memls" locksystem, and start a sweeper for it
+    /// (see `start_sweeper`).
+    pub fn with_sweeper(interval: Duration) -> Box<MemLs> {
+        let ls = MemLs::new();
+        ls.start_sweeper(interval);
+        ls
+    }
+
+    /// Start a background thread that calls `sweep` every `interval`,
+    /// so that expired locks do not pile up in memory.
+    ///
+    /// The thread only holds a weak reference to the locksystem. It stops
+    /// at the first tick after the last clone of this `MemLs` is dropped.
+    pub fn start_sweeper(&self, interval: Duration) {
+        let ls = Arc::downgrade(&self.0);
+        let res = std::thread::Builder::new()
+            .name("memls-sweeper".to_string())
+            .spawn(move || {
+                loop {
+                    std::thread::sleep(interval);
+                    if !sweep_weak(&ls) {
+                        break;
+                    }
+                }
+            });
+        if let Err(e) = res {
+            error!("memls: cannot start sweeper thread: {}", e);
+        }
+    }
+
+    /// Like `start_sweeper`, but runs as a task on the current tokio runtime
+    /// instead of in a thread. Must be called from within a runtime.
+    ///
+    /// The task stops when the last clone of this `MemLs` is dropped, or
+    /// when it is aborted with the returned `AbortHandle`.
+    pub fn spawn_sweeper_tokio(&self, interval: Duration) -> (tokio::task::JoinHandle<()>, AbortHandle) {
+        let ls = Arc::downgrade(&self.0);
+        let (task, abort_handle) = future::abortable(async move {
+            let mut interval = tokio::time::interval(interval);
+            // the first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if !sweep_weak(&ls) {
+                    break;
+                }
+            }
+        });
+        let join_handle = tokio::spawn(async move {
+            let _ = task.await;
+        });
+        (join_handle, abort_handle)
+    }
+
+    // Lock the state for reading. If another thread panicked while holding
+    // the lock, carry on anyway, so that one failed request does not make
+    // the locksystem unusable.
+    //
+    // The lock is tried without blocking first, so that we can cheaply
+    // tell whether we had to wait, for contention_stats.
+    #[cfg(not(feature = "memls-parking-lot"))]
+    fn read(&self) -> RwLockReadGuard<'_, MemLsInner> {
+        let (guard, waited) = match self.0.state.try_read() {
+            Ok(guard) => (guard, None),
+            Err(TryLockError::Poisoned(e)) => (e.into_inner(), None),
+            Err(TryLockError::WouldBlock) => {
+                self.0.contention.contended();
+                let start = Instant::now();
+                let guard = self.0.state.read().unwrap_or_else(|e| e.into_inner());
+                (guard, Some(start.elapsed()))
+            },
+        };
+        self.0.contention.record(waited);
+        guard
+    }
+
+    // Lock the state for writing. Works like `read`.
+    #[cfg(not(feature = "memls-parking-lot"))]
+    fn write(&self) -> RwLockWriteGuard<'_, MemLsInner> {
+        let (guard, waited) = match self.0.state.try_write() {
+            Ok(guard) => (guard, None),
+            Err(TryLockError::Poisoned(e)) => (e.into_inner(), None),
+            Err(TryLockError::WouldBlock) => {
+                self.0.contention.contended();
+                let start = Instant::now();
+                let guard = self.0.state.write().unwrap_or_else(|e| e.into_inner());
+                (guard, Some(start.elapsed()))
+            },
+        };
+        self.0.contention.record(waited);
+        guard
+    }
+
+    // Like the std versions, but there is no poisoning to deal with.
+    #[cfg(feature = "memls-parking-lot")]
+    fn read(&self) -> RwLockReadGuard<'_, MemLsInner> {
+        let (guard, waited) = match self.0.state.try_read() {
+            Some(guard) => (guard, None),
+            None => {
+                self.0.contention.contended();
+                let start = Instant::now();
+                (self.0.state.read(), Some(start.elapsed()))
+            },
+        };
+        self.0.contention.record(waited);
+        guard
+    }
+
+    #[cfg(feature = "memls-parking-lot")]
+    fn write(&self) -> RwLockWriteGuard<'_, MemLsInner> {
+        let (guard, waited) = match self.0.state.try_write() {
+            Some(guard) => (guard, None),
+            None => {
+                self.0.contention.contended();
+                let start = Instant::now();
+                (self.0.state.write(), Some(start.elapsed()))
+            },
+        };
+        self.0.contention.record(waited);
+        guard
+    }
+
+    /// How often the internal state lock was contended, and how long
+    /// threads waited for it in total, since the locksystem was created.
+    pub fn contention_stats(&self) -> ContentionStats {
+        let c = &self.0.contention;
+        ContentionStats {
+            acquisitions: c.acquisitions.load(Ordering::Relaxed),
+            contended:    c.contended.load(Ordering::Relaxed),
+            wait_time:    Duration::from_nanos(c.wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Remove all expired locks, and the tree nodes that are no longer needed.
+    ///
+    /// Returns the paths of the lock-null resources (see `lock_null`) whose
+    /// lock expired. The caller should remove those resources, if they
+    /// are still empty.
+    pub fn sweep(&self) -> Vec<DavPath> {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let now = inner.config.now();
+        let expired = prune_node(&mut inner.tree, tree::ROOT_ID, &|l: &DavLock| is_expired(l, now));
+        trace!("sweep: removed {} expired locks", expired.len());
+        let mut lock_null = Vec::new();
+        for lock in &expired {
+            if inner.lock_null.contains(&lock.token) {
+                lock_null.push(lock.path.clone());
+            }
+        }
+        inner.forget(&expired);
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            for lock in expired {
+                on_event(LockEvent::Expired(lock));
+            }
+        }
+        lock_null
+    }
+
+    /// Lock a path that is not mapped to a resource yet, in other words,
+    /// create a lock-null resource (RFC4918, section 7.3).
+    ///
+    /// This is `lock`, but it also returns whether the path was new to the
+    /// locksystem (there were no locks at or below it). The lock is
+    /// remembered as a lock-null lock: if it expires, `sweep` returns its
+    /// path, so that the resource can be cleaned up. Call `clear_lock_null`
+    /// once the resource has been written to.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lock_null(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(DavLock, bool), LockError>
+    {
+        self.do_lock(
+            path,
+            principal,
+            owner,
+            Expiry::After(timeout),
+            scope,
+            depth,
+            submitted_tokens,
+            HashMap::new(),
+            true,
+            false,
+        )
+    }
+
+    /// Like `lock`, but if `fail_if_any_existing` is set, the lock is
+    /// only granted on a pristine path: any lock on the path, on one of
+    /// its parents or (for a deep lock) below it makes it fail, even a
+    /// shared lock when a shared lock is requested.
+    ///
+    /// This is for clients that use a lock as an optimistic "nobody else
+    /// is working on this" check.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lock_conditional(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+        fail_if_any_existing: bool,
+    ) -> Result<DavLock, LockError>
+    {
+        self.do_lock(
+            path,
+            principal,
+            owner,
+            Expiry::After(timeout),
+            scope,
+            depth,
+            submitted_tokens,
+            HashMap::new(),
+            false,
+            fail_if_any_existing,
+        )
+        .map(|(lock, _)| lock)
+    }
+
+    /// Like `lock`, but the lock expires at `deadline` instead of after a
+    /// timeout. The expiry time is used as-is, it is not converted to a
+    /// duration and back. `MemLsConfig::max_timeout` still applies.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lock_until(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        deadline: SystemTime,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>
+    {
+        self.do_lock(
+            path,
+            principal,
+            owner,
+            Expiry::At(deadline),
+            scope,
+            depth,
+            submitted_tokens,
+            HashMap::new(),
+            false,
+            false,
+        )
+        .map(|(lock, _)| lock)
+    }
+
+    /// Like `refresh`, but the lock now expires at `deadline`. The
+    /// `RefreshPolicy` does not apply, `MemLsConfig::max_timeout` does.
+    pub fn refresh_until(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        deadline: SystemTime,
+    ) -> Result<DavLock, RefreshError>
+    {
+        self.do_refresh(path, principal, token, Expiry::At(deadline))
+    }
+
+    /// Like `check`, but if `MemLsConfig::strict_tokens` is set, the
+    /// submitted tokens are validated first. The first one that is not a
+    /// valid lock token is returned as `CheckError::MalformedToken`.
+    pub fn check_tokens(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), CheckError>
+    {
+        if self.read().config.strict_tokens {
+            if let Some(t) = submitted_tokens.iter().find(|t| LockToken::parse(t).is_err()) {
+                trace!("check: malformed token {:?}", t);
+                return Err(CheckError::MalformedToken(t.to_string()));
+            }
+        }
+        self.check(path, principal, ignore_principal, deep, submitted_tokens)
+            .map_err(|lock| CheckError::Locked(Box::new(lock)))
+    }
+
+    /// Like `lock`, with `extra` attached to the new lock as
+    /// `DavLock::extra`. It is returned by `discover` and the like,
+    /// and it stays with the lock when it is refreshed or moved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lock_with_extra(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+        extra: HashMap<String, String>,
+    ) -> Result<DavLock, LockError>
+    {
+        self.do_lock(
+            path,
+            principal,
+            owner,
+            Expiry::After(timeout),
+            scope,
+            depth,
+            submitted_tokens,
+            extra,
+            false,
+            false,
+        )
+        .map(|(lock, _)| lock)
+    }
+
+    /// Like `lock`, but a conflict is returned separately from the other
+    /// errors: `Ok(Err(conflict))` says which lock is in the way and how
+    /// it relates to `path`, the outer error is never `LockError::Conflict`.
+    ///
+    /// Like `lock`, the conflict checks and the creation of the lock are
+    /// one atomic step, unlike `would_lock` followed by `lock`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lock_or_conflict(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<Result<DavLock, LockConflict>, LockError>
+    {
+        match self.lock(path, principal, owner, timeout, scope, depth, submitted_tokens) {
+            Ok(lock) => Ok(Ok(lock)),
+            Err(LockError::Conflict(conflict)) => Ok(Err(*conflict)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Would `lock` with these arguments succeed? Returns the first lock
+    /// that would conflict, like `check` does, but nothing is created.
+    ///
+    /// Only conflicts with other locks are checked, not the limits of the
+    /// configuration (`max_locks_per_node` and the like), so `lock` can
+    /// still fail when this doesn't. And of course, another request can
+    /// take out a conflicting lock in the meantime.
+    ///
+    /// So this is advisory only. `lock` checks for conflicts and creates
+    /// the lock with the state locked, it is the only way to find out for
+    /// sure. Use `lock_or_conflict` if you need to know what is in the way.
+    pub fn would_lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let key = inner.config.tree_path(path);
+        let tokens = &submitted_tokens;
+        let (shared, deep) = (scope.is_shared(), depth.is_infinity());
+        lock_conflict(&inner.tree, &key, principal, tokens, shared, deep, now).map_err(|c| c.lock)
+    }
+
+    /// The resource at `path` is no longer a lock-null resource, for example
+    /// because it has been written to. Its locks stay, but `sweep` will not
+    /// report the path anymore when they expire.
+    pub fn clear_lock_null(&self, path: &DavPath) {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let key = inner.config.tree_path(path);
+        let tree = &inner.tree;
+        if let Some(node) = lookup_node(tree, &key).and_then(|n| tree.get_node(n).ok()) {
+            for lock in node {
+                inner.lock_null.remove(&lock.token);
+            }
+        }
+    }
+
+    /// Like `lock`, but returns a guard that unlocks the lock when
+    /// it is dropped.
+    pub fn lock_guard(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+    ) -> Result<LockGuard, LockError>
+    {
+        let lock = self.lock(path, principal, owner, timeout, scope, depth, Vec::new())?;
+        Ok(LockGuard {
+            ls: Arc::downgrade(&self.0),
+            lock,
+        })
+    }
+
+    /// Remove all locks created by `principal`, wherever they are.
+    /// Returns the number of locks that were removed.
+    ///
+    /// This is useful to clean up after a client session has ended.
+    pub fn unlock_all_by_principal(&self, principal: &str) -> usize {
+        self.release(|l| l.principal.as_deref() == Some(principal)).len()
+    }
+
+    /// Remove the lock with this token, wherever it is. Returns `false`
+    /// if there was no such lock.
+    pub fn unlock_by_token(&self, token: &str) -> bool {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let node_id = match inner.tokens.remove(token) {
+            Some(n) => n,
+            None => return false,
+        };
+        inner.lock_null.remove(token);
+        let lock = match inner.tree.get_node_mut(node_id) {
+            Ok(node) => {
+                match node.iter().position(|l| l.token == token) {
+                    Some(idx) => node.remove(idx),
+                    None => return false,
+                }
+            },
+            Err(_) => return false,
+        };
+        remove_empty_nodes(&mut inner.tree, node_id);
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            on_event(LockEvent::Released {
+                token: lock.token.to_string(),
+                path:  lock.path,
+            });
+        }
+        true
+    }
+
+    // The body of lock, lock_null and lock_conditional. Also returns
+    // whether the tree did not have a node for the path yet.
+    // With fail_if_any_existing, shared locks are checked as if an
+    // exclusive lock was requested.
+    #[allow(clippy::too_many_arguments)]
+    fn do_lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        expiry: Expiry,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+        extra: HashMap<String, String>,
+        lock_null: bool,
+        fail_if_any_existing: bool,
+    ) -> Result<(DavLock, bool), LockError>
+    {
+        // the segments end up as keys in the tree, and as hrefs in
+        // the lockdiscovery XML. keep control characters out of both.
+        if path.as_bytes().iter().any(|&b| b < 0x20) {
+            trace!("lock: control character in path {}", path.as_url_string());
+            return Err(LockError::InvalidPath);
+        }
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let now = inner.config.now();
+        let key = inner.config.tree_path(path);
+        let shared = scope.is_shared();
+        let deep = depth.is_infinity();
+        let shared_ok = shared && !fail_if_any_existing;
+
+        let tokens = &submitted_tokens;
+        lock_conflict(&inner.tree, &key, principal, tokens, shared_ok, deep, now)
+            .map_err(LockError::Conflict)?;
+
+        // room for one more in the whole locksystem?
+        if let Some(max) = inner.config.max_total_locks {
+            if inner.lock_count() >= max {
+                trace!("lock: locksystem already holds {} locks", inner.lock_count());
+                return Err(LockError::StorageFull);
+            }
+        }
+
+        // room for one more on this node?
+        if let Some(max) = inner.config.max_locks_per_node {
+            let count = lookup_node(&inner.tree, &key)
+                .and_then(|n| inner.tree.get_node(n).ok())
+                .map_or(0, |locks| locks.iter().filter(|l| !is_expired(l, now)).count());
+            if count >= max {
+                trace!("lock: {} already holds {} locks", path, count);
+                return Err(LockError::TooManyLocks);
+            }
+        }
+
+        // create lock. the token must be unique, with the default UUIDs
+        // a collision never happens, but a custom generator might be buggy.
+        let mut tries = 1;
+        let token = loop {
+            match inner.config.new_token() {
+                Ok(t) if !inner.tokens.contains_key(&t) => break t,
+                _ if tries == 3 => {
+                    trace!("lock: token generator did not return a valid token");
+                    return Err(LockError::InvalidToken);
+                },
+                _ => tries += 1,
+            }
+        };
+        let (timeout, deadline) = match expiry {
+            Expiry::After(timeout) => (inner.config.clamp_timeout(timeout), None),
+            Expiry::At(deadline) => (None, Some(inner.config.clamp_deadline(deadline, now))),
+        };
+        if timeout.is_none() && deadline.is_none() && inner.config.forbid_infinite_timeout {
+            trace!("lock: infinite timeout not allowed");
+            return Err(LockError::InfiniteTimeout);
+        }
+        if let (Some(max), Some(owner)) = (inner.config.max_owner_size, owner) {
+            // serializing is the only way to find out, so only do it once.
+            let size = owner_to_string(owner).map_or(usize::MAX, |o| o.len());
+            if size > max {
+                trace!("lock: owner is {} bytes, max is {}", size, max);
+                return Err(LockError::OwnerTooLarge);
+            }
+        }
+        // everything that can make the lock fail has been checked by now,
+        // so we never create nodes in the tree that end up without a lock.
+        let max_depth = inner.config.max_path_depth.unwrap_or(DEFAULT_MAX_PATH_DEPTH);
+        let created = lookup_node(&inner.tree, &key).is_none();
+        let node_id = match get_or_create_path_node(&mut inner.tree, &key, max_depth) {
+            Ok(node_id) => node_id,
+            Err(e) => {
+                trace!("lock: cannot create node for {}: {:?}", path, e);
+                return Err(e);
+            },
+        };
+        let mut builder = DavLock::builder(&path.normalize())
+            .token(token)
+            .principal(principal)
+            .owner(owner)
+            .timeout(timeout)
+            .scope(scope)
+            .depth(depth)
+            .extra(extra)
+            .created_at(now);
+        if let Some(deadline) = deadline {
+            builder = builder.timeout_at(deadline);
+        }
+        let lock = builder.build();
+        trace!("lock {} created", &lock.token);
+        inner.tree.get_node_mut(node_id).map_err(|_| LockError::Internal)?.push(lock.clone());
+        inner.tokens.insert(lock.token.clone(), node_id);
+        if lock_null {
+            inner.lock_null.insert(lock.token.clone());
+        }
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            on_event(LockEvent::Acquired(lock.clone()));
+        }
+        Ok((lock, created))
+    }
+
+    // The body of refresh and refresh_until.
+    fn do_refresh(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        expiry: Expiry,
+    ) -> Result<DavLock, RefreshError>
+    {
+        trace!("refresh lock {}", token);
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let key = inner.config.tree_path(path);
+        let node_id = match inner.lookup_lock(&key, token) {
+            None => {
+                trace!("lock not found");
+                return Err(RefreshError::NotLocked);
+            },
+            Some(n) => n,
+        };
+        let lock = match inner.tree.get_node_mut(node_id) {
+            Ok(node) => {
+                node.iter_mut()
+                    .find(|n| n.token.as_str() == token)
+                    .ok_or(RefreshError::NotLocked)?
+            },
+            Err(_) => return Err(RefreshError::NotLocked),
+        };
+        if inner.config.enforce_principal && lock.principal.as_deref() != principal {
+            trace!("lock is owned by {:?}, not by {:?}", lock.principal, principal);
+            return Err(RefreshError::PrincipalMismatch);
+        }
+        let now = inner.config.now();
+        let (timeout, timeout_at) = match expiry {
+            Expiry::After(timeout) => inner.config.refresh_timeout(lock, timeout, now),
+            Expiry::At(deadline) => {
+                let deadline = inner.config.clamp_deadline(deadline, now);
+                let timeout = deadline.duration_since(now).unwrap_or(Duration::new(0, 0));
+                (Some(timeout), Some(deadline))
+            },
+        };
+        lock.timeout = timeout;
+        lock.timeout_at = timeout_at;
+        lock.last_refreshed_at = now;
+        let lock = lock.clone();
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            on_event(LockEvent::Refreshed(lock.clone()));
+        }
+        Ok(lock)
+    }
+
+    /// Give the lock with token `old_token` at `path` a new token, without
+    /// releasing it, for example when handing a lock over to another server
+    /// instance. Returns the updated lock.
+    ///
+    /// Fails if there is no such lock, or if `new_token` is not a valid
+    /// lock token or is already in use.
+    #[allow(clippy::result_unit_err)]
+    pub fn rekey(&self, path: &DavPath, old_token: &str, new_token: &str) -> Result<DavLock, ()> {
+        let new_token = LockToken::parse(new_token).map_err(|_| ())?;
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        if inner.tokens.contains_key(&new_token) {
+            trace!("rekey: {} already exists", new_token);
+            return Err(());
+        }
+        let key = inner.config.tree_path(path);
+        let node_id = inner.lookup_lock(&key, old_token).ok_or(())?;
+
+        // every lock is stored once, at the node of its lock root.
+        let lock = match inner.tree.get_node_mut(node_id) {
+            Ok(node) => {
+                let lock = node.iter_mut().find(|l| l.token == old_token).ok_or(())?;
+                lock.token = new_token.clone();
+                lock.clone()
+            },
+            Err(_) => return Err(()),
+        };
+        inner.tokens.remove(old_token);
+        inner.tokens.insert(new_token.clone(), node_id);
+        if inner.lock_null.remove(old_token) {
+            inner.lock_null.insert(new_token);
+        }
+        Ok(lock)
+    }
+
+    // Remove the locks for which "remove" returns true, and send
+    // a Released event for every one of them.
+    fn release<F>(&self, remove: F) -> Vec<DavLock>
+    where F: Fn(&DavLock) -> bool {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let removed = prune_node(&mut inner.tree, tree::ROOT_ID, &remove);
+        inner.forget(&removed);
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            for lock in &removed {
+                on_event(LockEvent::Released {
+                    token: lock.token.to_string(),
+                    path:  lock.path.clone(),
+                });
+            }
+        }
+        removed
+    }
+
+    /// Remove all locks, and reset the locksystem to its initial state.
+    /// A `LockEvent::Released` is fired for every lock that is removed.
+    /// Returns the number of locks that were removed.
+    pub fn clear(&self) -> usize {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let mut removed = Vec::new();
+        for (_, node) in inner.tree.iter() {
+            removed.extend(node.iter().map(|l| (l.token.to_string(), l.path.clone())));
+        }
+        inner.tree.clear(Vec::new());
+        inner.tokens.clear();
+        inner.lock_null.clear();
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            for (token, path) in &removed {
+                on_event(LockEvent::Released {
+                    token: token.clone(),
+                    path:  path.clone(),
+                });
+            }
+        }
+        removed.len()
+    }
+
+    /// Return statistics about the locks that are currently held.
+    pub fn stats(&self) -> MemLsStats {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let mut stats = MemLsStats::default();
+        for (_, node) in inner.tree.iter() {
+            for lock in node.iter().filter(|l| !is_expired(l, now)) {
+                stats.locks += 1;
+                if lock.is_shared() {
+                    stats.shared += 1;
+                } else {
+                    stats.exclusive += 1;
+                }
+                if lock.is_deep() {
+                    stats.deep += 1;
+                }
+            }
+        }
+        stats.nodes = inner.tree.len();
+        stats
+    }
+
+    /// The number of distinct paths that hold at least one lock. Expired
+    /// locks are skipped. Unlike `MemLsStats::locks`, a resource with
+    /// several shared locks is only counted once.
+    ///
+    /// This walks the whole lock tree.
+    pub fn locked_path_count(&self) -> usize {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        inner
+            .tree
+            .iter()
+            .filter(|(_, node)| node.iter().any(|l| !is_expired(l, now)))
+            .count()
+    }
+
+    /// Returns `true` if there are no locks at all. Expired locks
+    /// count until they are removed by `sweep`.
+    pub fn is_empty(&self) -> bool {
+        let inner = &*self.read();
+        inner.tree.is_empty() && matches!(inner.tree.get_node(tree::ROOT_ID), Ok(l) if l.is_empty())
+    }
+
+    /// Return all locks that are currently held, sorted by path.
+    ///
+    /// Expired locks that have not been swept yet are not included.
+    pub fn all_locks(&self) -> Vec<DavLock> {
+        let mut locks = Vec::new();
+        self.for_each_lock(|_, lock| locks.push(lock.clone()));
+        locks.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        locks
+    }
+
+    /// Call `f` with the path and the lock, for every lock that is
+    /// currently held, in no particular order. Expired locks that have not
+    /// been swept yet are skipped.
+    ///
+    /// Unlike `all_locks` this does not collect the locks first, so it
+    /// scales to very large lock stores. The locksystem stays locked while
+    /// `f` runs, so `f` must not call back into the locksystem, or it
+    /// deadlocks.
+    pub fn for_each_lock<F>(&self, mut f: F)
+    where F: FnMut(&DavPath, &DavLock) {
+        self.visit_locks(|lock, expired| {
+            if !expired {
+                f(&lock.path, lock);
+            }
+        });
+    }
+
+    // Call `f` for every lock in the tree, including the expired ones,
+    // with the state locked for reading. The bool says if it has expired.
+    fn visit_locks<F>(&self, mut f: F)
+    where F: FnMut(&DavLock, bool) {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        for (_, node) in inner.tree.iter() {
+            for lock in node {
+                f(lock, is_expired(lock, now));
+            }
+        }
+    }
+
+    /// Return all locks created by `principal`, sorted by path.
+    ///
+    /// Expired locks that have not been swept yet are not included.
+    pub fn locks_by_principal(&self, principal: &str) -> Vec<DavLock> {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let owned = |l: &DavLock| l.principal.as_deref() == Some(principal) && !is_expired(l, now);
+        let mut locks = Vec::new();
+        for (_, node) in inner.tree.find(|node| node.iter().any(owned)) {
+            locks.extend(node.iter().filter(|l| owned(l)).cloned());
+        }
+        locks.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        locks
+    }
+
+    /// Like `discover`, but also returns where each lock comes from: is it
+    /// rooted at `path` itself, or is it a deep lock on an ancestor.
+    /// Locks on ancestors that are not deep do not cover `path` and
+    /// are not returned.
+    pub fn discover_detailed(&self, path: &DavPath) -> Vec<(DavLock, LockOrigin)> {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let path = inner.config.tree_path(path);
+        let segs = path_to_segs(&path, false);
+        let mut locks = Vec::new();
+        let mut node_id = tree::ROOT_ID;
+        for level in 0..=segs.len() {
+            if level > 0 {
+                node_id = match inner.tree.get_child(node_id, segs[level - 1]) {
+                    Ok(n) => n,
+                    Err(_) => break,
+                };
+            }
+            let up = segs.len() - level;
+            let origin = if up == 0 {
+                LockOrigin::Direct
+            } else {
+                LockOrigin::Inherited(up)
+            };
+            if let Ok(node) = inner.tree.get_node(node_id) {
+                for lock in node
+                    .iter()
+                    .filter(|l| (up == 0 || l.is_deep()) && !is_expired(l, now))
+                {
+                    locks.push((lock.clone(), origin));
+                }
+            }
+        }
+        locks
+    }
+
+    /// Like `discover`, but if `include_expired` is set, locks that have
+    /// expired but have not been swept yet are returned as well.
+    ///
+    /// This shows the raw state of the locksystem, which is useful
+    /// when debugging why a lock went away.
+    pub fn discover_all(&self, path: &DavPath, include_expired: bool) -> Vec<DavLock> {
+        let inner = &*self.read();
+        let path = inner.config.tree_path(path);
+        list_locks(&inner.tree, &path, inner.config.now(), include_expired)
+    }
+
+    /// Like `discover`, but only returns the locks on `path` itself and on
+    /// the parents at most `max_ancestor_depth` levels up. With a depth
+    /// of 1, that is just the immediate parent.
+    pub fn discover_within(&self, path: &DavPath, max_ancestor_depth: usize) -> Vec<DavLock> {
+        let inner = &*self.read();
+        let path = inner.config.tree_path(path);
+        let depth = path_to_segs(&path, false).len();
+        let now = inner.config.now();
+        let mut locks = Vec::new();
+        let mut node_id = tree::ROOT_ID;
+        for seg in path_to_segs(&path, true) {
+            node_id = match get_child(&inner.tree, node_id, seg) {
+                Ok(n) => n,
+                Err(_) => break,
+            };
+            let node_depth = inner.tree.depth(node_id).unwrap_or(0);
+            if depth - node_depth > max_ancestor_depth {
+                continue;
+            }
+            if let Ok(node) = inner.tree.get_node(node_id) {
+                locks.extend(node.iter().filter(|l| !is_expired(l, now)).cloned());
+            }
+        }
+        locks
+    }
+
+    /// Return only the locks that are rooted at `path` itself, not the ones
+    /// that are inherited from a parent. Expired locks are skipped.
+    pub fn locks_at(&self, path: &DavPath) -> Vec<DavLock> {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let path = inner.config.tree_path(path);
+        match lookup_node(&inner.tree, &path).and_then(|n| inner.tree.get_node(n).ok()) {
+            Some(locks) => locks.iter().filter(|l| !is_expired(l, now)).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The number of shared locks that are rooted at `path` itself, not
+    /// counting inherited ones. Expired locks are skipped.
+    pub fn shared_lock_count(&self, path: &DavPath) -> usize {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let path = inner.config.tree_path(path);
+        match lookup_node(&inner.tree, &path).and_then(|n| inner.tree.get_node(n).ok()) {
+            Some(locks) => {
+                locks
+                    .iter()
+                    .filter(|l| l.is_shared() && !is_expired(l, now))
+                    .count()
+            },
+            None => 0,
+        }
+    }
+
+    /// Return a snapshot of all locks, including expired ones that have
+    /// not been swept yet, sorted by path. Meant for debugging.
+    pub fn dump(&self) -> Vec<DavLockSnapshot> {
+        let mut locks = Vec::new();
+        self.visit_locks(|lock, expired| {
+            locks.push(DavLockSnapshot {
+                path: lock.path.with_prefix().as_url_string(),
+                token: lock.token.to_string(),
+                shared: lock.is_shared(),
+                deep: lock.is_deep(),
+                principal: lock.principal.clone(),
+                owner: lock.owner.as_ref().and_then(owner_to_string),
+                created_at: lock.created_at,
+                timeout_at: lock.timeout_at,
+                last_refreshed_at: lock.last_refreshed_at,
+                extra: lock.extra.clone(),
+                expired,
+            })
+        });
+        locks.sort_by(|a, b| a.path.cmp(&b.path));
+        locks
+    }
+
+    /// Return a rough estimate of the memory used by the lock tree, in bytes.
+    ///
+    /// For every node this counts the size of the node itself, plus
+    /// the entries in the hashmaps of the tree, plus its path segment.
+    /// For every lock it counts the size of `DavLock` plus the length of
+    /// its token, path, principal and extra data, and the size of the owner element
+    /// when serialized as XML. Allocator overhead and unused capacity
+    /// are not counted, so the real footprint is larger, but it grows
+    /// at the same rate.
+    pub fn memory_estimate(&self) -> usize {
+        let inner = &*self.read();
+        let node_size = mem::size_of::<tree::Node<Vec<u8>, Vec<DavLock>>>() +
+            mem::size_of::<(u64, tree::Node<Vec<u8>, Vec<DavLock>>)>() +
+            mem::size_of::<(Vec<u8>, u64)>();
+        let mut total = 0;
+        for (path, node) in inner.tree.iter() {
+            total += node_size + path.last().map_or(0, |seg| seg.len());
+            for lock in node {
+                total += mem::size_of::<DavLock>() +
+                    lock.token.len() +
+                    lock.path.with_prefix().as_bytes().len() +
+                    lock.principal.as_ref().map_or(0, |p| p.len()) +
+                    lock.extra.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>();
+                total += lock
+                    .owner
+                    .as_ref()
+                    .and_then(owner_to_string)
+                    .map_or(0, |o| o.len());
+            }
+        }
+        total
+    }
+
+    /// Move all locks at `from` and below to `to`, e.g. after a MOVE.
+    ///
+    /// The locks keep their tokens, only their paths change. That way a
+    /// client that holds a lock can keep using it after a rename.
+    /// Locks that were at `to` or below are removed. Fails if `from` or
+    /// `to` is the root, or if one is below the other, for example `/a/b`
+    /// to `/a` (`FsError::Forbidden`). In that case no locks are changed.
+    pub fn relocate(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.write().relocate(from, to, |_| false).map(|_| ())
+    }
+
+    /// Update the locks after a MOVE of `from` to `to`.
+    ///
+    /// A MOVE must not move the locks of a resource along with it
+    /// (RFC4918, 7.7), but servers differ in what they do with them. The
+    /// policy here is: the locks at `from` and below whose token is in
+    /// `submitted_tokens` are moved to `to`, like `relocate` does, so the
+    /// client that did the MOVE keeps the locks it proved to hold. All
+    /// other locks at `from` and below are removed, as are the locks that
+    /// were at `to` or below, and a `LockEvent::Released` is fired for each
+    /// of them.
+    ///
+    /// Locks on the parents of `from` are not touched. Fails like
+    /// `relocate`, in which case no locks are changed.
+    pub fn on_move(&self, from: &DavPath, to: &DavPath, submitted_tokens: Vec<&str>) -> FsResult<()> {
+        let mut guard = self.write();
+        let removed = guard.relocate(from, to, |l| !submitted_tokens.contains(&l.token.as_str()))?;
+        let on_event = guard.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            for lock in removed {
+                on_event(LockEvent::Released {
+                    token: lock.token.to_string(),
+                    path:  lock.path,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy all locks at `from` and below to `to`.
+    ///
+    /// Copying the locks verbatim would create duplicate tokens, so every
+    /// copy gets a new, unique token from the token generator. Expired locks
+    /// are not copied. Locks that were at `to` or below are removed. Fails
+    /// like `relocate`, with `FsError::InsufficientStorage` if the copies do
+    /// not fit within `max_total_locks` or `max_locks_per_node`, and with
+    /// `FsError::GeneralFailure` if the token generator does not come up
+    /// with unique tokens. If it fails, no locks are changed.
+    ///
+    /// Note that a WebDAV COPY does not copy locks (RFC4918, 7.6), so the
+    /// handler never calls this.
+    pub fn copy_locks(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let key_from = inner.config.tree_path(from);
+        let key_to = inner.config.tree_path(to);
+        let segs_from = path_to_segs(&key_from, false);
+        let mut segs_to = path_to_segs(&key_to, false);
+        if !relocatable(&segs_from, &segs_to) {
+            return Err(FsError::Forbidden);
+        }
+        let node_id = match lookup_node(&inner.tree, &key_from) {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+
+        // check the limits.
+        let now = inner.config.now();
+        let mut count = 0;
+        let mut stack = vec![node_id];
+        while let Some(id) = stack.pop() {
+            let n = inner
+                .tree
+                .get_node(id)
+                .map_or(0, |locks| locks.iter().filter(|l| !is_expired(l, now)).count());
+            if matches!(inner.config.max_locks_per_node, Some(max) if n > max) {
+                trace!("copy_locks: {} locks on one node", n);
+                return Err(FsError::InsufficientStorage);
+            }
+            count += n;
+            if let Ok(children) = inner.tree.get_children(id) {
+                stack.extend(children.map(|(_, child_id)| child_id));
+            }
+        }
+        if let Some(max) = inner.config.max_total_locks {
+            let replaced = lookup_node(&inner.tree, &key_to)
+                .map_or(0, |n| subtree_locks(&inner.tree, n).len());
+            if inner.lock_count() - replaced + count > max {
+                trace!("copy_locks: {} more locks do not fit", count);
+                return Err(FsError::InsufficientStorage);
+            }
+        }
+
+        // a new token for every copy.
+        let mut tokens = Vec::with_capacity(count);
+        while tokens.len() < count {
+            let mut tries = 1;
+            let token = loop {
+                match inner.config.new_token() {
+                    Ok(t) if !inner.tokens.contains_key(&t) && !tokens.contains(&t) => break t,
+                    _ if tries == 3 => {
+                        trace!("copy_locks: token generator did not return a valid token");
+                        return Err(FsError::GeneralFailure);
+                    },
+                    _ => tries += 1,
+                }
+            };
+            tokens.push(token);
+        }
+
+        // find or create the new parent, then replace the destination.
+        let name = segs_to.pop().unwrap().to_vec();
+        let parent_id = get_or_create_nodes(&mut inner.tree, &segs_to)?;
+        if let Ok(dest_id) = inner.tree.get_child(parent_id, &name) {
+            inner.delete_subtree(dest_id);
+        }
+
+        let tokens = RefCell::new(tokens);
+        let skip = segs_from.len();
+        let copy = |locks: &Vec<DavLock>| {
+            locks
+                .iter()
+                .filter(|l| !is_expired(l, now))
+                .map(|l| {
+                    let mut lock = l.clone();
+                    // there is exactly one token for every copied lock.
+                    lock.token = tokens.borrow_mut().pop().unwrap_or_else(LockToken::generate);
+                    lock.path = rebase_path(&l.path, to, skip);
+                    lock
+                })
+                .collect()
+        };
+        let new_id = inner.tree.copy_subtree(node_id, parent_id, name, copy)?;
+        index_subtree(&inner.tree, new_id, &mut inner.tokens);
+        // the copy can have empty nodes, if locks were not copied.
+        prune_node(&mut inner.tree, new_id, &|_: &DavLock| false);
+        remove_empty_nodes(&mut inner.tree, parent_id);
+        Ok(())
+    }
+
+    /// Save all locks to `w`, as JSON.
+    #[cfg(feature = "memls-serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "memls-serde")))]
+    pub fn save_to<W: Write>(&self, w: W) -> io::Result<()> {
+        let inner = &*self.read();
+        let mut locks = Vec::new();
+        for (_, node) in inner.tree.iter() {
+            locks.extend_from_slice(node);
+        }
+        let mut lock_null: Vec<_> = inner.lock_null.iter().cloned().collect();
+        lock_null.sort();
+        serde_json::to_writer(w, &SavedState { locks, lock_null })?;
+        Ok(())
+    }
+
+    /// Create a new "memls" locksystem with the default configuration
+    /// from locks saved earlier by `save_to`. See `load_from_with`.
+    #[cfg(feature = "memls-serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "memls-serde")))]
+    pub fn load_from<R: Read>(r: R) -> io::Result<Box<MemLs>> {
+        MemLs::load_from_with(MemLsConfig::new(), r)
+    }
+
+    /// Create a new "memls" locksystem with configuration `config`
+    /// from locks saved earlier by `save_to`.
+    ///
+    /// Locks that have expired in the meantime are dropped. Fails with
+    /// `io::ErrorKind::InvalidData` if a lock has a duplicate token, a
+    /// path that is not a valid URL path, or a path that is deeper than
+    /// `max_path_depth` allows.
+    #[cfg(feature = "memls-serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "memls-serde")))]
+    pub fn load_from_with<R: Read>(config: MemLsConfig, r: R) -> io::Result<Box<MemLs>> {
+        let (locks, lock_null) = match serde_json::from_reader(r)? {
+            SavedLocks::State(state) => (state.locks, state.lock_null),
+            // saved by a version that only saved the locks.
+            SavedLocks::Locks(locks) => (locks, Vec::new()),
+        };
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let ls = config.build();
+        {
+            let inner = &mut *ls.write();
+            let now = inner.config.now();
+            let max_depth = inner.config.max_path_depth.unwrap_or(DEFAULT_MAX_PATH_DEPTH);
+            for mut lock in locks.into_iter().filter(|l| !is_expired(l, now)) {
+                if lock.last_refreshed_at < lock.created_at {
+                    // saved by a version that did not record refreshes.
+                    lock.last_refreshed_at = lock.created_at;
+                }
+                if inner.tokens.contains_key(&lock.token) {
+                    return Err(invalid(format!("duplicate lock token {}", lock.token)));
+                }
+                let key = inner.config.tree_path(&lock.path).into_owned();
+                let node_id = get_or_create_path_node(&mut inner.tree, &key, max_depth)
+                    .map_err(|e| invalid(format!("cannot load lock on {}: {:?}", lock.path, e)))?;
+                inner.tokens.insert(lock.token.clone(), node_id);
+                inner.tree.get_node_mut(node_id).map_err(FsError::from)?.push(lock);
+            }
+            let tokens = &inner.tokens;
+            inner.lock_null = lock_null.into_iter().filter(|t| tokens.contains_key(t)).collect();
+        }
+        Ok(ls)
+    }
+}
+
+// The format of MemLs::save_to.
+#[cfg(feature = "memls-serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedState {
+    locks:     Vec<DavLock>,
+    #[serde(default)]
+    lock_null: Vec<LockToken>,
+}
+
+// What MemLs::load_from accepts: the current format, or just the
+// list of locks, which is what older versions saved.
+#[cfg(feature = "memls-serde")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum SavedLocks {
+    State(SavedState),
+    Locks(Vec<DavLock>),
+}
+
+impl DavLockSystem for MemLs {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>
+    {
+        self.do_lock(
+            path,
+            principal,
+            owner,
+            Expiry::After(timeout),
+            scope,
+            depth,
+            submitted_tokens,
+            HashMap::new(),
+            false,
+            false,
+        )
+        .map(|(lock, _)| lock)
+    }
+
+    fn unlock(&self, path: &DavPath, principal: Option<&str>, token: &str) -> Result<(), UnlockError> {
+        let mut guard = self.write();
+        let inner = &mut *guard;
+        let key = inner.config.tree_path(path);
+        let node_id = match inner.lookup_lock(&key, token) {
+            None => {
+                trace!("unlock: {} not found at {}", token, path);
+                let now = inner.config.now();
+                let rc = check_locks_to_path(&inner.tree, &key, None, true, &Vec::new(), false, false, now);
+                if rc.is_ok() {
+                    return Err(UnlockError::NotLocked);
+                }
+                return Err(UnlockError::TokenMismatch);
+            },
+            Some(n) => n,
+        };
+        let lock_path = {
+            let lock = match inner.tree.get_node(node_id) {
+                Ok(node) => node.iter().find(|n| n.token.as_str() == token),
+                Err(_) => None,
+            };
+            let lock = lock.ok_or(UnlockError::NotLocked)?;
+            // UNLOCK must be done on the lock-root, not on a path below it.
+            // a trailing slash does not matter, "/a/b" and "/a/b/" are the same.
+            let lock_root = inner.config.tree_path(&lock.path);
+            if !lock_root.eq_ignore_trailing_slash(&key.normalize()) {
+                trace!("unlock: {} is locked at {}, not at {}", token, lock.path, path);
+                return Err(UnlockError::TokenMismatch);
+            }
+            if inner.config.enforce_principal && lock.principal.as_deref() != principal {
+                trace!(
+                    "unlock: {} is owned by {:?}, not by {:?}",
+                    token,
+                    lock.principal,
+                    principal
+                );
+                return Err(UnlockError::PrincipalMismatch);
+            }
+            lock.path.clone()
+        };
+        // every lock is stored once, at the node of its lock root,
+        // deep or not.
+        let node = inner
+            .tree
+            .get_node_mut(node_id)
+            .map_err(|_| UnlockError::NotLocked)?;
+        node.retain(|n| n.token.as_str() != token);
+        remove_empty_nodes(&mut inner.tree, node_id);
+        inner.tokens.remove(token);
+        inner.lock_null.remove(token);
+        let on_event = inner.config.on_event.clone();
+        drop(guard);
+        if let Some(on_event) = on_event {
+            on_event(LockEvent::Released {
+                token: token.to_string(),
+                path:  lock_path,
+            });
+        }
+        Ok(())
+    }
+
+    fn refresh(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, RefreshError>
+    {
+        self.do_refresh(path, principal, token, Expiry::After(timeout))
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        self.check_all(path, principal, ignore_principal, deep, submitted_tokens)
+            .map_err(|mut v| v.remove(0))
+    }
+
+    fn check_all(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), Vec<DavLock>>
+    {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        let path = &*inner.config.tree_path(path);
+        let conflicts = check_path(
+            &inner.tree,
+            path,
+            principal,
+            ignore_principal,
+            deep,
+            &submitted_tokens,
+            now,
+        );
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(conflicts)
+        }
+    }
+
+    fn check_many(
+        &self,
+        paths: &[(&DavPath, bool)],
+        principal: Option<&str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        for &(path, deep) in paths {
+            let path = &*inner.config.tree_path(path);
+            let mut conflicts = check_path(
+                &inner.tree,
+                path,
+                principal,
+                ignore_principal,
+                deep,
+                &submitted_tokens,
+                now,
+            );
+            if !conflicts.is_empty() {
+                return Err(conflicts.remove(0));
+            }
+        }
+        Ok(())
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        let inner = &*self.read();
+        let path = inner.config.tree_path(path);
+        list_locks(&inner.tree, &path, inner.config.now(), false)
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        let inner = &mut *self.write();
+        if let Some(node_id) = lookup_node(&inner.tree, &inner.config.tree_path(path)) {
+            if node_id == tree::ROOT_ID {
+                // the root node itself cannot be deleted.
+                for (_, child_id) in inner.tree.get_children(node_id).map_err(|_| ())? {
+                    inner.delete_subtree(child_id);
+                }
+                let locks = mem::take(inner.tree.get_node_mut(node_id).map_err(|_| ())?);
+                inner.forget(&locks);
+            } else {
+                let parent_id = inner.tree.get_parent(node_id).map_err(|_| ())?;
+                inner.delete_subtree(node_id);
+                remove_empty_nodes(&mut inner.tree, parent_id);
+            }
+        }
+        Ok(())
+    }
+
+    // by token, so that locks that were moved by relocate are found.
+    fn has_token(&self, token: &str, _path: &DavPath) -> bool {
+        let inner = &*self.read();
+        let now = inner.config.now();
+        match inner.tokens.get(token).and_then(|&n| inner.tree.get_node(n).ok()) {
+            Some(node) => node.iter().any(|l| l.token == token && !is_expired(l, now)),
+            None => false,
+        }
+    }
+}
+
+// Would a new lock on the path conflict with an existing one? Returns
+// the first conflict. Locks whose tokens were submitted do not count.
+fn lock_conflict(
+    tree: &Tree,
+    path: &DavPath,
+    principal: Option<&str>,
+    submitted_tokens: &Vec<&str>,
+    shared_ok: bool,
+    deep: bool,
+    now: SystemTime,
+) -> Result<(), Box<LockConflict>>
+{
+    // any locks in the path?
+    let rc = check_locks_to_path(tree, path, principal, false, submitted_tokens, shared_ok, false, now);
+    trace!("lock: check_locks_to_path: {:?}", rc);
+    rc.map_err(|mut v| Box::new(v.remove(0)))?;
+
+    // if it's a deep lock we need to check if there are locks furter along the path.
+    if deep {
+        let rc = check_locks_from_path(tree, path, principal, false, submitted_tokens, shared_ok, now);
+        trace!("lock: check_locks_from_path: {:?}", rc);
+        rc.map_err(|mut v| Box::new(v.remove(0)))?;
+    }
+    Ok(())
+}
+
+// Check the path for conflicting locks, and if "deep" is set, everything below it.
+fn check_path(
+    tree: &Tree,
+    path: &DavPath,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    deep: bool,
+    submitted_tokens: &Vec<&str>,
+    now: SystemTime,
+) -> Vec<DavLock>
+{
+    let rc = check_locks_to_path(
+        tree,
+        path,
+        principal,
+        ignore_principal,
+        submitted_tokens,
+        false,
+        true,
+        now,
+    );
+    trace!("check: check_lock_to_path: {:?}: {:?}", submitted_tokens, rc);
+    let mut conflicts: Vec<DavLock> = rc.err().unwrap_or_default().into_iter().map(|c| c.lock).collect();
+
+    // if it's a deep lock we need to check if there are locks furter along the path.
+    if deep {
+        let rc = check_locks_from_path(
+            tree,
+            path,
+            principal,
+            ignore_principal,
+            submitted_tokens,
+            false,
+            now,
+        );
+        trace!("check: check_locks_from_path: {:?}", rc);
+        // the node at "path" itself is visited by both checks.
+        for c in rc.err().unwrap_or_default() {
+            if !conflicts.contains(&c.lock) {
+                conflicts.push(c.lock);
+            }
+        }
+    }
+
+    conflicts
+}
+
+// check if there are any locks along the path. Returns all conflicting locks,
+// the exclusive locks first, shallowest (closest to the root) first.
+// If "holder_ok" is set, holding any lock on the path is enough to get
+// past the shared ones (for writes, not for taking a new lock).
+#[allow(clippy::too_many_arguments)]
+fn check_locks_to_path(
+    tree: &Tree,
+    path: &DavPath,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &Vec<&str>,
+    shared_ok: bool,
+    holder_ok: bool,
+    now: SystemTime,
+) -> Result<(), Vec<LockConflict>>
+{
+    // path segments
+    let segs = path_to_segs(path, true);
+    let last_seg = segs.len() - 1;
+
+    // state
+    let mut holds_lock = false;
+    let mut conflicts = Vec::new();
+    let mut shared_locks_seen: Vec<LockConflict> = Vec::new();
+
+    // walk over path segments starting at root.
+    let mut node_id = tree::ROOT_ID;
+    for (i, seg) in segs.into_iter().enumerate() {
+        node_id = match get_child(tree, node_id, seg) {
+            Ok(n) => n,
+            Err(_) => break,
+        };
+        let node_locks = match tree.get_node(node_id) {
+            Ok(n) => n,
+            Err(_) => break,
+        };
+
+        let relation = if i < last_seg {
+            ConflictRelation::Ancestor
+        } else {
+            ConflictRelation::Self_
+        };
+        for nl in node_locks {
+            if (i < last_seg && !nl.is_deep()) || is_expired(nl, now) {
+                continue;
+            }
+            let held = held_tokens(nl, principal, ignore_principal, submitted_tokens);
+            if held.contains(&nl.token.as_str()) {
+                // fine, we hold this lock.
+                holds_lock = true;
+            } else if lock_conflicts(nl, shared_ok, false, true, held) {
+                let conflict = LockConflict {
+                    lock: nl.to_owned(),
+                    relation,
+                };
+                if nl.is_shared() {
+                    // remember shared locks seen.
+                    shared_locks_seen.push(conflict);
+                } else {
+                    // exclusive locks are fatal.
+                    conflicts.push(conflict);
+                }
+            }
+        }
+    }
+
+    // shared locks only conflict if we do not hold any of the locks.
+    if !(holder_ok && holds_lock) {
+        conflicts.extend(shared_locks_seen);
+    }
+
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(conflicts)
+    }
+}
+
+// See if there are locks in any path below this collection.
+// For "/" this is the root node, so the whole tree is checked.
+fn check_locks_from_path(
+    tree: &Tree,
+    path: &DavPath,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &Vec<&str>,
+    shared_ok: bool,
+    now: SystemTime,
+) -> Result<(), Vec<LockConflict>>
+{
+    let node_id = match lookup_node(tree, path) {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let conflicts = check_locks_from_node(
+        tree,
+        node_id,
+        principal,
+        ignore_principal,
+        submitted_tokens,
+        shared_ok,
+        now,
+    );
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(conflicts)
+    }
+}
+
+// See if there are locks in this node or any nodes below it.
+// Returns the conflicting locks, shallowest first, exclusive locks
+// before shared locks at the same depth.
+fn check_locks_from_node(
+    tree: &Tree,
+    node_id: u64,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &Vec<&str>,
+    shared_ok: bool,
+    now: SystemTime,
+) -> Vec<LockConflict>
+{
+    let start_id = node_id;
+    let mut conflicts = Vec::new();
+    // no recursion, the tree can be deep. breadth-first, so that
+    // the shallowest conflict comes first.
+    let mut queue = VecDeque::from(vec![node_id]);
+    while let Some(node_id) = queue.pop_front() {
+        let node_locks = match tree.get_node(node_id) {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        let exclusive_first = node_locks.iter().filter(|l| !l.is_shared());
+        let shared = node_locks.iter().filter(|l| l.is_shared());
+        for nl in exclusive_first.chain(shared) {
+            if is_expired(nl, now) {
+                continue;
+            }
+            let held = held_tokens(nl, principal, ignore_principal, submitted_tokens);
+            if lock_conflicts(nl, shared_ok, true, false, held) {
+                let relation = if node_id == start_id {
+                    ConflictRelation::Self_
+                } else {
+                    ConflictRelation::Descendant
+                };
+                conflicts.push(LockConflict {
+                    lock: nl.to_owned(),
+                    relation,
+                });
+            }
+        }
+        if let Ok(children) = tree.get_children(node_id) {
+            queue.extend(children.map(|(_, id)| id));
+        }
+    }
+    conflicts
+}
+
+// Find or create the node for these segments, and return its id.
+// If it fails, no new nodes are left in the tree.
+fn get_or_create_nodes(tree: &mut Tree, segs: &[&[u8]]) -> TreeResult<u64> {
+    let mut node_id = tree::ROOT_ID;
+    for seg in segs {
+        node_id = match tree.get_child(node_id, *seg) {
+            Ok(n) => n,
+            Err(_) => {
+                match tree.add_child(node_id, seg.to_vec(), Vec::new(), false) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        // do not leave the nodes that we created so far behind.
+                        remove_empty_nodes(tree, node_id);
+                        return Err(e);
+                    },
+                }
+            },
+        };
+    }
+    Ok(node_id)
+}
+
+// Find or create node, and return its id. Fails with PathTooDeep if the path has
+// more than max_depth segments. If it fails, no new nodes are left in the tree.
+fn get_or_create_path_node(tree: &mut Tree, path: &DavPath, max_depth: usize) -> Result<u64, LockError> {
+    let segs = path_to_segs(path, false);
+    if segs.len() > max_depth {
+        return Err(LockError::PathTooDeep);
+    }
+    get_or_create_nodes(tree, &segs).map_err(|_| LockError::Internal)
+}
+
+// One round of a sweeper: sweep if the locksystem still exists.
+// Returns false if it is gone and the sweeper should stop.
+fn sweep_weak(ls: &Weak<StateLock>) -> bool {
+    match ls.upgrade() {
+        Some(inner) => {
+            MemLs(inner).sweep();
+            true
+        },
+        None => false,
+    }
+}
+
+// Can the locks at "from" be moved or copied to "to"? Not from or to the
+// root, and not if one path is below the other (or they are the same):
+// the destination subtree is replaced, which would destroy the source.
+fn relocatable(segs_from: &[&[u8]], segs_to: &[&[u8]]) -> bool {
+    !segs_from.is_empty() &&
+        !segs_to.is_empty() &&
+        !segs_to.starts_with(segs_from) &&
+        !segs_from.starts_with(segs_to)
+}
+
+// Find node ID for path.
+fn lookup_node(tree: &Tree, path: &DavPath) -> Option<u64> {
+    let mut node_id = tree::ROOT_ID;
+    for seg in path_to_segs(path, false) {
+        node_id = match tree.get_child(node_id, seg) {
+            Ok(n) => n,
+            Err(_) => return None,
+        };
+    }
+    Some(node_id)
+}
+
+// Find all locks in a path. Expired locks are skipped, unless include_expired is set.
+//
+// This walks the path the same way check_locks_to_path does: the root
+// is the node for the empty first segment, and it is visited exactly
+// once. Locks on "/" are on the root node, so they are found for every
+// path (and for "/" itself), just like the locks on any other parent.
+fn list_locks(tree: &Tree, path: &DavPath, now: SystemTime, include_expired: bool) -> Vec<DavLock> {
+    let mut locks = Vec::new();
+    let wanted = |l: &&DavLock| include_expired || !is_expired(l, now);
+
+    let mut node_id = tree::ROOT_ID;
+    for seg in path_to_segs(path, true) {
+        node_id = match get_child(tree, node_id, seg) {
+            Ok(n) => n,
+            Err(_) => break,
+        };
+        if let Ok(node) = tree.get_node(node_id) {
+            locks.extend(node.iter().filter(wanted).cloned());
+        }
+    }
+    locks
+}
+
+// Remove the locks for which "remove" returns true from this node and all
+// nodes below it. Nodes that end up without locks and without children are
+// deleted (except the root). Returns the locks that were removed.
+fn prune_node<F>(tree: &mut Tree, node_id: u64, remove: &F) -> Vec<DavLock>
+where F: Fn(&DavLock) -> bool {
+    // collect the nodes top-down, then visit them bottom-up.
+    let mut nodes = Vec::new();
+    let mut stack = vec![node_id];
+    while let Some(id) = stack.pop() {
+        nodes.push(id);
+        if let Ok(children) = tree.get_children(id) {
+            stack.extend(children.map(|(_, child_id)| child_id));
+        }
+    }
+    let mut removed = Vec::new();
+    for id in nodes.into_iter().rev() {
+        let len = match tree.get_node_mut(id) {
+            Ok(node) => {
+                let (gone, kept) = node.drain(..).partition::<Vec<_>, _>(|l| remove(l));
+                removed.extend(gone);
+                *node = kept;
+                node.len()
+            },
+            Err(_) => continue,
+        };
+        if len == 0 && id != tree::ROOT_ID {
+            // fails if the node still has children, which is what we want.
+            tree.delete_node(id).ok();
+        }
+    }
+    removed
+}
+
+// Delete this node if it has no locks and no children, then do
+// the same for its parent, and so on. The root is never deleted.
+fn remove_empty_nodes(tree: &mut Tree, mut node_id: u64) {
+    while node_id != tree::ROOT_ID {
+        if !matches!(tree.get_node(node_id), Ok(l) if l.is_empty()) {
+            break;
+        }
+        let parent_id = match tree.get_parent(node_id) {
+            Ok(p) => p,
+            Err(_) => break,
+        };
+        // fails if the node still has children.
+        if tree.delete_node(node_id).is_err() {
+            break;
+        }
+        node_id = parent_id;
+    }
+}
+
+// Serialize the owner element of a lock as XML.
+fn owner_to_string(owner: &Element) -> Option<String> {
+    let mut buf = Vec::new();
+    let config = EmitterConfig::new().write_document_declaration(false);
+    owner.write_with_config(&mut buf, config).ok()?;
+    String::from_utf8(buf).ok()
+}
+
+// The locks in this node and all nodes below it.
+fn subtree_locks(tree: &Tree, node_id: u64) -> Vec<DavLock> {
+    let mut locks = Vec::new();
+    let mut stack = vec![node_id];
+    while let Some(id) = stack.pop() {
+        if let Ok(node) = tree.get_node(id) {
+            locks.extend(node.iter().cloned());
+        }
+        if let Ok(children) = tree.get_children(id) {
+            stack.extend(children.map(|(_, child_id)| child_id));
+        }
+    }
+    locks
+}
+
+// Add the locks in this node and all nodes below it to a token index.
+fn index_subtree(tree: &Tree, node_id: u64, tokens: &mut HashMap<LockToken, u64>) {
+    let mut stack = vec![node_id];
+    while let Some(id) = stack.pop() {
+        if let Ok(node) = tree.get_node(id) {
+            tokens.extend(node.iter().map(|l| (l.token.clone(), id)));
+        }
+        if let Ok(children) = tree.get_children(id) {
+            stack.extend(children.map(|(_, child_id)| child_id));
+        }
+    }
+}
+
+// Has this lock timed out?
+fn is_expired(lock: &DavLock, now: SystemTime) -> bool {
+    matches!(lock.timeout_at, Some(t) if t < now)
+}
+
+// The lock compatibility matrix.
+//
+// Does the `existing` lock conflict with a new lock (or, with
+// `requested_shared` false, a write) on some path? `along_path` is true
+// if `existing` is on that path itself or on a parent that it covers,
+// and false if it is on a member below that path. `held_tokens` are the
+// tokens the requester holds *and* is allowed to use for `existing`.
+//
+// - a lock that the requester holds never conflicts.
+// - locks below the path only matter for a deep request.
+// - shared locks are compatible with shared locks, everything else conflicts.
+//
+// Expiry and whether a lock on a parent actually covers the path (it must
+// be deep) are up to the caller, as is the rule that holding any of the
+// locks on the path is enough to get past the shared ones.
+fn lock_conflicts(
+    existing: &DavLock,
+    requested_shared: bool,
+    requested_deep: bool,
+    along_path: bool,
+    held_tokens: &[&str],
+) -> bool
+{
+    if held_tokens.iter().any(|t| existing.token == *t) {
+        return false;
+    }
+    if !along_path && !requested_deep {
+        return false;
+    }
+    !(existing.is_shared() && requested_shared)
+}
+
+// The submitted tokens that may be used for this lock: none at all
+// if the lock belongs to another principal.
+fn held_tokens<'a>(
+    lock: &DavLock,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &'a [&'a str],
+) -> &'a [&'a str]
+{
+    if ignore_principal || principal == lock.principal.as_deref() {
+        submitted_tokens
+    } else {
+        &[]
+    }
+}
+
+// The path of a lock after the node at depth "skip" moved to "to".
+fn rebase_path(path: &DavPath, to: &DavPath, skip: usize) -> DavPath {
+    let mut newpath = to.normalize();
+    for seg in path_to_segs(path, false).into_iter().skip(skip) {
+        newpath.push_segment(seg);
+    }
+    newpath.add_slash_if(path.is_collection());
+    newpath
+}
+
+// Split path into segments, using DavPath::segments(_with_root).
+// "." and ".." are resolved like DavPath::normalize does.
+fn path_to_segs(path: &DavPath, include_root: bool) -> Vec<&[u8]> {
+    let mut segs: Vec<&[u8]> = Vec::new();
+    if include_root {
+        segs.push(b"");
+    }
+    let skip = segs.len();
+    for seg in path.segments() {
+        match seg {
+            b"." => {},
+            b".." => {
+                if segs.len() > skip {
+                    segs.pop();
+                }
+            },
+            s => segs.push(s),
+        }
+    }
+    segs
+}
+
+fn get_child(tree: &Tree, node_id: u64, seg: &[u8]) -> TreeResult<u64> {
+    if seg.len() == 0 {
+        return Ok(node_id);
+    }
+    tree.get_child(node_id, seg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmltree_ext::ElementExt;
+    use LockDepth::{Infinity, Zero};
+    use LockScope::{Exclusive, Shared};
+
+    fn p(s: &str) -> DavPath {
+        DavPath::new(s).unwrap()
+    }
+
+    #[test]
+    fn unlock_must_use_lock_root() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert_eq!(
+            ls.unlock(&p("/a/b"), None, &lock.token),
+            Err(UnlockError::TokenMismatch)
+        );
+        assert_eq!(ls.unlock(&p("/a"), None, "foo"), Err(UnlockError::TokenMismatch));
+        assert!(ls.unlock(&p("/a"), None, &lock.token).is_ok());
+        assert_eq!(
+            ls.unlock(&p("/a"), None, &lock.token),
+            Err(UnlockError::NotLocked)
+        );
+        ls.lock(&p("/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.unlock(&p("/b/c"), None, "foo"), Err(UnlockError::NotLocked));
+    }
+
+    #[test]
+    fn clones_share_state() {
+        let a = MemLs::default();
+        let b = a.clone();
+        let lock = a
+            .lock(&p("/a"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        let conflict = b.check(&p("/a/b"), Some("y"), false, false, vec![]).unwrap_err();
+        assert_eq!(conflict.token, lock.token);
+        assert_eq!(b.discover(&p("/a")), vec![lock.clone()]);
+        b.unlock(&p("/a"), None, &lock.token).unwrap();
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    #[cfg(not(feature = "memls-parking-lot"))]
+    fn poisoned() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let ls2 = ls.clone();
+        let res = std::thread::spawn(move || {
+            let _guard = ls2.0.state.write().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(res.is_err());
+        assert!(ls.0.state.is_poisoned());
+        assert_eq!(ls.discover(&p("/a")).len(), 1);
+        ls.unlock(&p("/a"), None, &lock.token).unwrap();
+        ls.lock(&p("/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+    }
+
+    #[test]
+    fn max_locks_per_node() {
+        let ls = MemLs::builder().max_locks_per_node(2).build();
+        assert!(ls.lock(&p("/a"), None, None, None, Shared, Zero, vec![]).is_ok());
+        assert!(ls.lock(&p("/a"), None, None, None, Shared, Zero, vec![]).is_ok());
+        match ls.lock(&p("/a"), None, None, None, Shared, Zero, vec![]) {
+            Err(LockError::TooManyLocks) => {},
+            other => panic!("expected TooManyLocks, got {:?}", other),
+        }
+        assert!(ls.lock(&p("/b"), None, None, None, Shared, Zero, vec![]).is_ok());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let ls = MemLs::builder().case_insensitive(true).build();
+        let lock = ls
+            .lock(&p("/Foo"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls.check(&p("/foo/bar"), None, false, false, vec![]).is_err());
+        let locks = ls.discover(&p("/FOO"));
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].path.as_bytes(), b"/Foo");
+        assert!(ls.unlock(&p("/fOO"), None, &lock.token).is_ok());
+
+        let ls = MemLs::new();
+        ls.lock(&p("/Foo"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls.check(&p("/foo"), None, false, false, vec![]).is_ok());
+    }
+
+    #[test]
+    fn all_locks() {
+        let ls = MemLs::new();
+        for path in &["/b/c", "/a", "/b", "/a/d/e"] {
+            ls.lock(&p(path), None, None, None, Shared, Zero, vec![]).unwrap();
+        }
+        let paths: Vec<_> = ls
+            .all_locks()
+            .iter()
+            .map(|l| l.path.as_bytes().to_vec())
+            .collect();
+        assert_eq!(paths, vec![&b"/a"[..], b"/a/d/e", b"/b", b"/b/c"]);
+    }
+
+    #[test]
+    fn deep_path() {
+        let path = p(&"/a".repeat(10_000));
+        let ls = MemLs::new();
+        match ls.lock(&path, None, None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::PathTooDeep) => {},
+            other => panic!("expected PathTooDeep, got {:?}", other),
+        }
+        assert!(ls.check(&path, None, false, true, vec![]).is_ok());
+
+        let ls = MemLs::builder().max_path_depth(10_000).build();
+        let lock = ls
+            .lock(&path, None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls.check(&p("/"), None, false, true, vec![]).is_err());
+        assert!(ls.unlock(&path, None, &lock.token).is_ok());
+        assert_eq!(ls.stats().nodes, 1);
+    }
+
+    #[test]
+    fn relocate() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/b/"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/x/y"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(ls.relocate(&p("/a/"), &p("/a/b/c")).is_err());
+        // to an ancestor: nothing changes.
+        assert_eq!(ls.relocate(&p("/a/b/"), &p("/a/")), Err(FsError::Forbidden));
+        assert_eq!(ls.discover(&p("/a/b/")), vec![lock.clone()]);
+        assert_eq!(ls.stats().locks, 2);
+        ls.relocate(&p("/a/"), &p("/x/")).unwrap();
+        assert!(ls.discover(&p("/a/b/")).is_empty());
+        let locks = ls.discover(&p("/x/b/"));
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].token, lock.token);
+        assert_eq!(locks[0].path.as_bytes(), b"/x/b/");
+        assert!(ls.discover(&p("/x/y")).is_empty());
+        assert!(ls.unlock(&p("/x/b/"), None, &lock.token).is_ok());
+    }
+
+    #[test]
+    fn copy_locks() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let lock = ls
+            .lock(&p("/a/b/"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        ls.lock(
+            &p("/a/c"),
+            None,
+            None,
+            Some(Duration::from_secs(10)),
+            Exclusive,
+            Zero,
+            vec![],
+        )
+        .unwrap();
+        clock.advance(11);
+        ls.copy_locks(&p("/a"), &p("/d/e")).unwrap();
+        let copies = ls.discover(&p("/d/e/b"));
+        assert_eq!(copies.len(), 1);
+        assert_ne!(copies[0].token, lock.token);
+        assert_eq!(copies[0].path.as_bytes(), b"/d/e/b/");
+        assert_eq!(copies[0].principal.as_deref(), Some("x"));
+        assert!(ls.discover_all(&p("/d/e/c"), true).is_empty());
+        assert_eq!(ls.discover(&p("/a/b")), vec![lock.clone()]);
+        assert!(ls.copy_locks(&p("/a"), &p("/a/x")).is_err());
+        // to an ancestor: fails, and the source is left alone.
+        assert_eq!(ls.copy_locks(&p("/d/e"), &p("/d")), Err(FsError::Forbidden));
+        assert_eq!(ls.discover(&p("/d/e/b")), copies);
+    }
+
+    #[test]
+    fn copy_locks_limits() {
+        let ls = MemLs::builder().max_total_locks(3).build();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/a/c"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        assert_eq!(
+            ls.copy_locks(&p("/a"), &p("/x")),
+            Err(FsError::InsufficientStorage)
+        );
+        assert_eq!(ls.stats().locks, 2);
+
+        // a constant token generator cannot make unique copies.
+        let ls = MemLs::builder()
+            .token_generator(|| "urn:uuid:constant".to_string())
+            .build();
+        let lock = ls
+            .lock(&p("/a/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.copy_locks(&p("/a"), &p("/x")), Err(FsError::GeneralFailure));
+        assert_eq!(ls.all_locks(), vec![lock]);
+    }
+
+    #[test]
+    fn memory_estimate() {
+        let ls = MemLs::new();
+        let empty = ls.memory_estimate();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        let one = ls.memory_estimate();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        let two = ls.memory_estimate();
+        assert!(empty < one && one < two);
+    }
+
+    #[test]
+    fn token_generator() {
+        let ls = MemLs::builder()
+            .token_generator(|| format!("opaquelocktoken:{}", Uuid::new_v4()))
+            .build();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(lock.token.starts_with("opaquelocktoken:"));
+
+        let ls = MemLs::builder().token_generator(String::new).build();
+        match ls.lock(&p("/a"), None, None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::InvalidToken) => {},
+            other => panic!("expected InvalidToken, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counter_tokens() {
+        let ls = MemLs::builder().counter_tokens().build();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.token, "urn:uuid:00000000-0000-0000-0000-000000000001");
+        let lock = ls
+            .lock(&p("/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.token, "urn:uuid:00000000-0000-0000-0000-000000000002");
+    }
+
+    #[test]
+    fn duplicate_token() {
+        let ls = MemLs::builder()
+            .token_generator(|| "opaquelocktoken:same".to_string())
+            .build();
+        assert!(ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .is_ok());
+        match ls.lock(&p("/b"), None, None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::InvalidToken) => {},
+            other => panic!("expected InvalidToken, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refresh_policy() {
+        let secs = Duration::from_secs;
+        let ls = MemLs::builder()
+            .refresh_policy(RefreshPolicy::ExtendFromCurrentExpiry)
+            .build();
+        let lock = ls
+            .lock(&p("/a"), None, None, Some(secs(100)), Exclusive, Zero, vec![])
+            .unwrap();
+        let lock2 = ls.refresh(&p("/a"), None, &lock.token, Some(secs(100))).unwrap();
+        assert!(lock2.timeout_at.unwrap() >= lock.timeout_at.unwrap() + secs(100));
+        let lock3 = ls.refresh(&p("/a"), None, &lock.token, None).unwrap();
+        assert_eq!(lock3.timeout, Some(secs(100)));
+
+        let ls = MemLs::builder()
+            .refresh_policy(RefreshPolicy::CapAtMax)
+            .max_timeout(secs(150))
+            .build();
+        let lock = ls
+            .lock(&p("/a"), None, None, Some(secs(100)), Exclusive, Zero, vec![])
+            .unwrap();
+        let lock2 = ls.refresh(&p("/a"), None, &lock.token, Some(secs(100))).unwrap();
+        assert!(lock2.timeout_at.unwrap() <= SystemTime::now() + secs(150));
+        assert!(lock2.timeout_at.unwrap() > lock.timeout_at.unwrap());
+    }
+
+    #[test]
+    fn discover_detailed() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a"), None, None, None, Shared, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b/c"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        let locks = ls.discover_detailed(&p("/a/b/c"));
+        let origins: Vec<_> = locks.iter().map(|(_, o)| *o).collect();
+        assert_eq!(origins, vec![LockOrigin::Inherited(2), LockOrigin::Direct]);
+        assert_eq!(locks[0].0.path.as_bytes(), b"/a");
+    }
+
+    #[derive(Clone)]
+    struct TestClock(Arc<std::sync::Mutex<SystemTime>>);
+
+    impl TestClock {
+        fn new() -> TestClock {
+            TestClock(Arc::new(std::sync::Mutex::new(SystemTime::UNIX_EPOCH)))
+        }
+
+        fn advance(&self, secs: u64) {
+            *self.0.lock().unwrap() += Duration::from_secs(secs);
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[cfg(feature = "memls-serde")]
+    #[test]
+    fn save_and_load() {
+        let clock = TestClock::new();
+        let config = || MemLs::builder().case_insensitive(true).clock(clock.clone());
+        let ls = config().build();
+        let lock = ls
+            .lock(&p("/A/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let timeout = Some(Duration::from_secs(10));
+        ls.lock_null(&p("/c"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        let mut buf = Vec::new();
+        ls.save_to(&mut buf).unwrap();
+
+        let ls = MemLs::load_from_with(config(), &buf[..]).unwrap();
+        assert_eq!(ls.discover(&p("/a/B")), vec![lock.clone()]);
+        assert_eq!(ls.discover(&p("/a/B"))[0].path.as_bytes(), b"/A/b");
+        clock.advance(11);
+        assert_eq!(ls.sweep(), vec![p("/c")]);
+
+        // the old format, just the locks.
+        let old = serde_json::to_vec(&vec![lock.clone()]).unwrap();
+        let ls = MemLs::load_from_with(config(), &old[..]).unwrap();
+        assert_eq!(ls.discover(&p("/a/b")), vec![lock]);
+
+        // the configuration applies.
+        let err = MemLs::load_from_with(config().max_path_depth(1), &buf[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "memls-serde")]
+    #[test]
+    fn save_and_load_paths() {
+        let ls = MemLs::new();
+        let path = DavPath::from_str_and_prefix("/pfx/a%20b", "/pfx").unwrap();
+        let lock = ls
+            .lock(&path, None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let mut buf = Vec::new();
+        ls.save_to(&mut buf).unwrap();
+        let json = String::from_utf8(buf.clone()).unwrap();
+        assert!(json.contains(r#""path":{"url":"/pfx/a%20b","prefix":"/pfx"}"#));
+
+        let ls = MemLs::load_from(&buf[..]).unwrap();
+        let locks = ls.discover(&p("/a b"));
+        assert_eq!(locks, vec![lock]);
+        assert_eq!(locks[0].path.prefix(), "/pfx");
+        assert_eq!(locks[0].path.with_prefix().as_url_string(), "/pfx/a%20b");
+
+        // a corrupted path is refused, it does not end up in the tree.
+        for bad in &[
+            r#""/../a""#,
+            r#""a""#,
+            r#"{"url":"/a","prefix":"/pfx"}"#,
+            r#"{"fullpath":[47,97],"pfxlen":10}"#,
+        ] {
+            let json = json.replace(r#"{"url":"/pfx/a%20b","prefix":"/pfx"}"#, bad);
+            let err = MemLs::load_from(json.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn last_refreshed_at() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.last_refreshed_at, SystemTime::UNIX_EPOCH);
+        clock.advance(7);
+        let lock = ls.refresh(&p("/a"), None, &lock.token, None).unwrap();
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(7);
+        assert_eq!(lock.last_refreshed_at, t);
+        assert_eq!(lock.created_at, SystemTime::UNIX_EPOCH);
+        assert_eq!(ls.discover(&p("/a"))[0].last_refreshed_at, t);
+    }
+
+    #[test]
+    fn lock_until() {
+        let clock = TestClock::new();
+        let ls = MemLs::builder()
+            .clock(clock.clone())
+            .max_timeout(Duration::from_secs(60))
+            .build();
+        let deadline = SystemTime::UNIX_EPOCH + Duration::new(30, 123);
+        let lock = ls
+            .lock_until(&p("/a"), None, None, deadline, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.timeout_at, Some(deadline));
+        assert_eq!(lock.timeout, Some(Duration::new(30, 123)));
+
+        clock.advance(10);
+        let deadline = SystemTime::UNIX_EPOCH + Duration::new(50, 7);
+        let lock = ls.refresh_until(&p("/a"), None, &lock.token, deadline).unwrap();
+        assert_eq!(lock.timeout_at, Some(deadline));
+        assert_eq!(lock.timeout, Some(Duration::new(40, 7)));
+
+        // max_timeout still applies.
+        let deadline = SystemTime::UNIX_EPOCH + Duration::from_secs(3600);
+        let lock = ls.refresh_until(&p("/a"), None, &lock.token, deadline).unwrap();
+        assert_eq!(lock.timeout_at, Some(SystemTime::UNIX_EPOCH + Duration::from_secs(70)));
+        assert_eq!(lock.timeout, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn clock() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let timeout = Some(Duration::from_secs(10));
+        let lock = ls
+            .lock(&p("/a"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.created_at, SystemTime::UNIX_EPOCH);
+        clock.advance(5);
+        assert!(ls.check(&p("/a"), None, false, false, vec![]).is_err());
+        let lock = ls.refresh(&p("/a"), None, &lock.token, timeout).unwrap();
+        assert_eq!(
+            lock.timeout_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(15))
+        );
+        clock.advance(11);
+        assert!(ls.check(&p("/a"), None, false, false, vec![]).is_ok());
+        assert_eq!(ls.discover_all(&p("/a"), true).len(), 1);
+        ls.sweep();
+        assert!(ls.is_empty());
+    }
+
+    #[test]
+    fn unlock_all_by_principal() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a"), Some("x"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/a"), Some("y"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/b/c"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let lock = ls
+            .lock(&p("/d"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.unlock_all_by_principal("x"), 2);
+        assert_eq!(ls.unlock_all_by_principal("x"), 0);
+        assert_eq!(ls.discover(&p("/a"))[0].principal.as_deref(), Some("y"));
+        assert!(ls.discover(&p("/b/c")).is_empty());
+        assert!(ls.unlock_by_token(&lock.token));
+        assert!(!ls.unlock_by_token(&lock.token));
+        assert_eq!(ls.stats().locks, 1);
+    }
+
+    #[test]
+    fn token_index() {
+        let ls = MemLs::new();
+        let a = ls
+            .lock(&p("/a/"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![&a.token])
+            .unwrap();
+        ls.copy_locks(&p("/a/"), &p("/c/")).unwrap();
+        ls.relocate(&p("/c/"), &p("/d/")).unwrap();
+        let rekeyed = ls.rekey(&p("/a/"), &a.token, "urn:uuid:rekeyed").unwrap();
+        assert!(ls.unlock_by_token(&rekeyed.token));
+        assert!(!ls.unlock_by_token(&a.token));
+        ls.delete(&p("/d/b")).unwrap();
+        let inner = ls.read();
+        assert_eq!(inner.lock_count(), 2);
+        for (token, &node_id) in &inner.tokens {
+            let node = inner.tree.get_node(node_id).unwrap();
+            assert!(node.iter().any(|l| &l.token == token));
+        }
+    }
+
+    #[test]
+    fn deep_lock_conflict_has_descendant_path() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/b/c"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        match ls.lock(&p("/a"), Some("y"), None, None, Exclusive, Infinity, vec![]) {
+            Err(LockError::Conflict(c)) => {
+                assert_eq!(c.lock.token, lock.token);
+                assert_eq!(c.lock.path.as_bytes(), b"/a/b/c");
+                assert_eq!(c.relation, ConflictRelation::Descendant);
+            },
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        let l = ls.check(&p("/a"), Some("y"), false, true, vec![]).unwrap_err();
+        assert_eq!(l.path.as_bytes(), b"/a/b/c");
+    }
+
+    #[test]
+    fn deep_lock_on_root() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/b"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        match ls.lock(&p("/"), Some("y"), None, None, Exclusive, Infinity, vec![]) {
+            Err(LockError::Conflict(c)) => {
+                assert_eq!(c.lock.token, lock.token);
+                assert_eq!(c.relation, ConflictRelation::Descendant);
+            },
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(ls.check(&p("/"), Some("y"), false, true, vec![]).is_err());
+        assert!(ls.check(&p("/"), Some("y"), false, false, vec![]).is_ok());
+        ls.unlock(&p("/a/b"), Some("x"), &lock.token).unwrap();
+
+        // a lock on the root is found for "/" by both list_locks and the
+        // from-node check, and it is reported exactly once.
+        let root = ls
+            .lock(&p("/"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert_eq!(ls.discover(&p("/")), vec![root.clone()]);
+        assert_eq!(ls.discover(&p("/a/b")), vec![root.clone()]);
+        let inner = ls.read();
+        let now = inner.config.now();
+        let tokens = vec![];
+        let root_id = tree::ROOT_ID;
+        let conflicts = check_locks_from_node(&inner.tree, root_id, Some("y"), false, &tokens, false, now);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].lock, root);
+        assert_eq!(conflicts[0].relation, ConflictRelation::Self_);
+        drop(inner);
+        assert_eq!(ls.check(&p("/"), Some("y"), false, true, vec![]).unwrap_err(), root);
+    }
+
+    #[test]
+    fn shallowest_conflict_first() {
+        let ls = MemLs::new();
+        let a = ls
+            .lock(&p("/a/"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        let ab = ls
+            .lock(&p("/a/b/"), Some("x"), None, None, Exclusive, Zero, vec![&a.token])
+            .unwrap();
+        ls.lock(&p("/a/b/c/d"), Some("x"), None, None, Shared, Zero, vec![&a.token])
+            .unwrap();
+
+        // ancestors: walked from the root down.
+        match ls.lock(&p("/a/b/"), Some("y"), None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::Conflict(c)) => assert_eq!(c.lock, a),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(ls.check(&p("/a/b/"), Some("y"), false, false, vec![]), Err(a.clone()));
+        let all = ls.check_all(&p("/a/b/"), Some("y"), false, false, vec![]);
+        assert_eq!(all, Err(vec![a.clone(), ab.clone()]));
+
+        // descendants: breadth-first.
+        ls.unlock(&p("/a/"), Some("x"), &a.token).unwrap();
+        for path in &["/a/b/c/e", "/b/c/d", "/c/d/e"] {
+            ls.lock(&p(path), Some("x"), None, None, Exclusive, Zero, vec![])
+                .unwrap();
+        }
+        match ls.lock(&p("/"), Some("y"), None, None, Exclusive, Infinity, vec![]) {
+            Err(LockError::Conflict(c)) => assert_eq!(c.lock, ab),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(ls.check(&p("/"), Some("y"), false, true, vec![]), Err(ab));
+    }
+
+    #[test]
+    fn nested_lock_by_owner() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls
+            .lock(&p("/a/b"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .is_err());
+        let tokens = vec![lock.token.as_str()];
+        assert!(ls
+            .lock(&p("/a/b"), Some("y"), None, None, Exclusive, Zero, tokens.clone())
+            .is_err());
+        let nested = ls
+            .lock(&p("/a/b"), Some("x"), None, None, Exclusive, Zero, tokens)
+            .unwrap();
+        assert_eq!(ls.discover(&p("/a/b")), vec![lock, nested]);
+    }
+
+    #[test]
+    fn exclusive_over_shared_with_own_token() {
+        let ls = MemLs::new();
+        let l1 = ls
+            .lock(&p("/a"), Some("u1"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        let l2 = ls
+            .lock(&p("/a"), Some("u2"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        // holding one of the shared locks does not excuse the other one.
+        let tokens = vec![l1.token.as_str()];
+        match ls.lock(&p("/a"), Some("u1"), None, None, Exclusive, Zero, tokens.clone()) {
+            Err(LockError::Conflict(c)) => assert_eq!(c.lock, l2),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(ls.discover(&p("/a")).len(), 2);
+        // writing is still fine for a holder of one of the shared locks.
+        assert!(ls.check(&p("/a"), Some("u1"), false, false, tokens).is_ok());
+    }
+
+    #[test]
+    fn lock_guard() {
+        let ls = MemLs::builder().enforce_principal(true).build();
+        let guard = ls
+            .lock_guard(&p("/a"), Some("x"), None, None, Exclusive, Zero)
+            .unwrap();
+        assert_eq!(ls.discover(&p("/a")), vec![guard.lock().clone()]);
+        assert!(ls
+            .lock_guard(&p("/a"), Some("y"), None, None, Exclusive, Zero)
+            .is_err());
+        drop(guard);
+        assert!(ls.is_empty());
+
+        // the lock is released after it was moved, too.
+        let guard = ls
+            .lock_guard(&p("/a"), Some("x"), None, None, Exclusive, Zero)
+            .unwrap();
+        ls.relocate(&p("/a"), &p("/b")).unwrap();
+        drop(guard);
+        assert_eq!(ls.read().lock_count(), 0);
+        assert!(ls.is_empty());
+
+        let guard = ls
+            .lock_guard(&p("/a"), None, None, None, Exclusive, Zero)
+            .unwrap();
+        drop(ls);
+        drop(guard);
+    }
+
+    #[test]
+    fn locks_by_principal() {
+        let ls = MemLs::new();
+        let l1 = ls
+            .lock(&p("/b"), Some("x"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/b"), Some("y"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        let l2 = ls
+            .lock(&p("/a/c"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.locks_by_principal("x"), vec![l2, l1]);
+        assert!(ls.locks_by_principal("z").is_empty());
+    }
+
+    #[test]
+    fn timeout_overflow() {
+        let ls = MemLs::new();
+        let huge = Some(Duration::from_secs(u64::MAX));
+        let lock = ls
+            .lock(&p("/a"), None, None, huge, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.timeout_at, None);
+        let lock = ls.refresh(&p("/a"), None, &lock.token, huge).unwrap();
+        assert_eq!(lock.timeout_at, None);
+        assert!(ls.check(&p("/a"), None, false, false, vec![]).is_err());
+
+        let ls = MemLs::builder()
+            .refresh_policy(RefreshPolicy::ExtendFromCurrentExpiry)
+            .build();
+        let timeout = Some(Duration::from_secs(60));
+        let lock = ls
+            .lock(&p("/a"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        let lock = ls.refresh(&p("/a"), None, &lock.token, huge).unwrap();
+        assert_eq!(lock.timeout_at, None);
+    }
+
+    #[test]
+    fn max_total_locks() {
+        let ls = MemLs::builder().max_total_locks(3).build();
+        let l1 = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/b/c"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/b/d"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        match ls.lock(&p("/e"), None, None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::StorageFull) => {},
+            other => panic!("expected StorageFull, got {:?}", other),
+        }
+        ls.unlock(&p("/a"), None, &l1.token).unwrap();
+        ls.lock(&p("/e"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.delete(&p("/b")).unwrap();
+        ls.lock(&p("/f"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/g"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(ls
+            .lock(&p("/h"), None, None, None, Exclusive, Zero, vec![])
+            .is_err());
+        assert_eq!(ls.read().lock_count(), ls.stats().locks);
+    }
+
+    #[test]
+    fn refresh_one_of_several_shared_locks() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let timeout = Some(Duration::from_secs(60));
+        let l1 = ls
+            .lock(&p("/a"), None, None, timeout, Shared, Zero, vec![])
+            .unwrap();
+        let l2 = ls
+            .lock(&p("/a"), None, None, timeout, Shared, Zero, vec![])
+            .unwrap();
+        clock.advance(30);
+        let refreshed = ls.refresh(&p("/a"), None, &l2.token, timeout).unwrap();
+        assert_eq!(refreshed.token, l2.token);
+        assert_eq!(
+            refreshed.timeout_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(90))
+        );
+        let locks = ls.discover(&p("/a"));
+        assert_eq!(locks.len(), 2);
+        let first = locks.iter().find(|l| l.token == l1.token).unwrap();
+        assert_eq!(first.timeout_at, l1.timeout_at);
+        let second = locks.iter().find(|l| l.token == l2.token).unwrap();
+        assert_eq!(second.timeout_at, refreshed.timeout_at);
+    }
+
+    #[test]
+    fn dump() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let owner = Element::parse(&b"<D:owner xmlns:D=\"DAV:\">me</D:owner>"[..]).unwrap();
+        let timeout = Some(Duration::from_secs(10));
+        ls.lock(
+            &p("/b b"),
+            Some("x"),
+            Some(&owner),
+            timeout,
+            Exclusive,
+            Infinity,
+            vec![],
+        )
+        .unwrap();
+        ls.lock(&p("/a"), None, None, None, Shared, Zero, vec![]).unwrap();
+        clock.advance(11);
+        let dump = ls.dump();
+        assert_eq!(dump.len(), 2);
+        assert_eq!(dump[0].path, "/a");
+        assert!(dump[0].shared && !dump[0].deep && !dump[0].expired);
+        assert_eq!(dump[1].path, "/b%20b");
+        assert!(dump[1].expired);
+        assert_eq!(dump[1].principal.as_deref(), Some("x"));
+        assert!(dump[1].owner.as_ref().unwrap().contains(">me</"));
+    }
+
+    #[test]
+    fn discover_all() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        ls.lock(
+            &p("/a"),
+            None,
+            None,
+            Some(Duration::from_secs(10)),
+            Shared,
+            Infinity,
+            vec![],
+        )
+        .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        clock.advance(11);
+        assert_eq!(ls.discover(&p("/a/b")).len(), 1);
+        assert_eq!(ls.discover_all(&p("/a/b"), false).len(), 1);
+        assert_eq!(ls.discover_all(&p("/a/b"), true).len(), 2);
+        ls.sweep();
+        assert_eq!(ls.discover_all(&p("/a/b"), true).len(), 1);
+    }
+
+    #[test]
+    fn shared_lock_holder_can_write() {
+        let ls = MemLs::new();
+        let l1 = ls
+            .lock(&p("/a"), Some("user1"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        let _l2 = ls
+            .lock(&p("/a"), Some("user2"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        // holding one of the shared locks is enough.
+        assert!(ls
+            .check(&p("/a"), Some("user1"), false, false, vec![&l1.token])
+            .is_ok());
+        // but not if you are someone else.
+        assert!(ls
+            .check(&p("/a"), Some("user3"), false, false, vec![&l1.token])
+            .is_err());
+        // no token, no write.
+        assert!(ls.check(&p("/a"), Some("user1"), false, false, vec![]).is_err());
+
+        // same for a shared deep lock on a parent.
+        let l3 = ls
+            .lock(&p("/b"), Some("user1"), None, None, Shared, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/b/c"), Some("user2"), None, None, Shared, Zero, vec![])
+            .unwrap();
+        assert!(ls
+            .check(&p("/b/c"), Some("user1"), false, false, vec![&l3.token])
+            .is_ok());
+    }
+
+    #[test]
+    fn shared_deep_lock_over_shared_locks() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        assert!(ls
+            .lock(&p("/a"), None, None, None, Shared, Infinity, vec![])
+            .is_ok());
+
+        let ls = MemLs::new();
+        ls.lock(&p("/a/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(ls
+            .lock(&p("/a"), None, None, None, Shared, Infinity, vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn check_many() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let (a, c) = (p("/a"), p("/c"));
+        assert!(ls
+            .check_many(&[(&c, true), (&a, false)], None, false, vec![])
+            .is_ok());
+        let conflict = ls
+            .check_many(&[(&c, true), (&a, true)], None, false, vec![])
+            .unwrap_err();
+        assert_eq!(conflict, lock);
+        assert!(ls
+            .check_many(&[(&c, true), (&a, true)], None, false, vec![&lock.token])
+            .is_ok());
+    }
+
+    #[test]
+    fn enforce_principal() {
+        let ls = MemLs::builder().enforce_principal(true).build();
+        let lock = ls
+            .lock(&p("/a"), Some("user1"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(
+            ls.refresh(&p("/a"), Some("user2"), &lock.token, None),
+            Err(RefreshError::PrincipalMismatch)
+        );
+        assert!(ls.refresh(&p("/a"), Some("user1"), &lock.token, None).is_ok());
+        assert_eq!(
+            ls.unlock(&p("/a"), Some("user2"), &lock.token),
+            Err(UnlockError::PrincipalMismatch)
+        );
+        assert!(ls.unlock(&p("/a"), Some("user1"), &lock.token).is_ok());
+
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a"), Some("user1"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(ls.unlock(&p("/a"), Some("user2"), &lock.token).is_ok());
+    }
+
+    #[test]
+    fn is_empty() {
+        let ls = MemLs::new();
+        assert!(ls.is_empty());
+        let l1 = ls
+            .lock(&p("/a/b/c"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let l2 = ls
+            .lock(&p("/d/e"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(!ls.is_empty());
+        ls.unlock(&p("/a/b/c"), None, &l1.token).unwrap();
+        ls.unlock(&p("/d/e"), None, &l2.token).unwrap();
+        assert!(ls.is_empty());
+
+        ls.lock(&p("/"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        assert!(!ls.is_empty());
+        ls.delete(&p("/")).unwrap();
+        assert!(ls.is_empty());
+    }
+
+    #[test]
+    fn on_event() {
+        use std::sync::Mutex;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let ev = events.clone();
+        let clock = TestClock::new();
+        let ls = MemLs::builder()
+            .clock(clock.clone())
+            .on_event(move |e| ev.lock().unwrap().push(e))
+            .build();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.refresh(&p("/a"), None, &lock.token, None).unwrap();
+        ls.unlock(&p("/a"), None, &lock.token).unwrap();
+        ls.lock(
+            &p("/b"),
+            None,
+            None,
+            Some(Duration::from_secs(10)),
+            Exclusive,
+            Zero,
+            vec![],
+        )
+        .unwrap();
+        clock.advance(11);
+        ls.sweep();
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0], LockEvent::Acquired(ref l) if l.token == lock.token));
+        assert!(matches!(events[1], LockEvent::Refreshed(ref l) if l.token == lock.token));
+        assert!(matches!(events[2], LockEvent::Released { ref token, .. } if *token == lock.token));
+        assert!(matches!(events[3], LockEvent::Acquired(_)));
+        assert!(matches!(events[4], LockEvent::Expired(ref l) if l.path.as_bytes() == b"/b"));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[test]
+    fn forbid_infinite_timeout() {
+        let ls = MemLs::builder().forbid_infinite_timeout(true).build();
+        match ls.lock(&p("/a"), None, None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::InfiniteTimeout) => {},
+            other => panic!("expected InfiniteTimeout, got {:?}", other),
+        }
+        let secs = Duration::from_secs;
+        let lock = ls
+            .lock(&p("/a"), None, None, Some(secs(60)), Exclusive, Zero, vec![])
+            .unwrap();
+        let lock = ls.refresh(&p("/a"), None, &lock.token, None).unwrap();
+        assert_eq!(lock.timeout, Some(secs(60)));
+
+        let ls = MemLs::builder()
+            .forbid_infinite_timeout(true)
+            .max_timeout(secs(120))
+            .build();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(lock.timeout, Some(secs(120)));
+    }
+
+    #[test]
+    fn lock_conflicts_matrix() {
+        let lock = |shared| DavLock::builder(&p("/a")).token("t").shared(shared).build();
+        let (excl, shrd) = (lock(false), lock(true));
+        // (existing, requested_shared, requested_deep, along_path, conflicts)
+        let matrix = [
+            (&excl, false, false, true, true),
+            (&excl, false, true, true, true),
+            (&excl, true, false, true, true),
+            (&excl, true, true, true, true),
+            (&excl, false, false, false, false),
+            (&excl, false, true, false, true),
+            (&excl, true, false, false, false),
+            (&excl, true, true, false, true),
+            (&shrd, false, false, true, true),
+            (&shrd, false, true, true, true),
+            (&shrd, true, false, true, false),
+            (&shrd, true, true, true, false),
+            (&shrd, false, false, false, false),
+            (&shrd, false, true, false, true),
+            (&shrd, true, false, false, false),
+            (&shrd, true, true, false, false),
+        ];
+        for &(existing, rshared, rdeep, along, expect) in &matrix {
+            let res = lock_conflicts(existing, rshared, rdeep, along, &[]);
+            assert_eq!(res, expect, "{:?}", (existing.is_shared(), rshared, rdeep, along));
+            // holding the token always makes the conflict go away.
+            assert!(!lock_conflicts(existing, rshared, rdeep, along, &["x", "t"]));
+            assert_eq!(lock_conflicts(existing, rshared, rdeep, along, &["x"]), expect);
+        }
+    }
+
+    #[test]
+    fn lock_null_expires_in_sweep() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let timeout = Some(Duration::from_secs(60));
+        let (_, created) = ls
+            .lock_null(&p("/a"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(created);
+        let (_, created) = ls
+            .lock_null(&p("/b"), None, None, timeout, Shared, Zero, vec![])
+            .unwrap();
+        assert!(created);
+        let (_, created) = ls
+            .lock_null(&p("/b"), None, None, timeout, Shared, Zero, vec![])
+            .unwrap();
+        assert!(!created);
+        ls.lock(&p("/c"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+
+        // /b was written to, so it is a normal resource now.
+        ls.clear_lock_null(&p("/b"));
+        clock.advance(61);
+        let paths = ls.sweep();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].as_bytes(), b"/a");
+        assert!(ls.is_empty());
+        assert!(ls.read().lock_null.is_empty());
+    }
+
+    #[test]
+    fn conflict_relation() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a/"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        let relation = |path| {
+            match ls.lock(&p(path), Some("y"), None, None, Exclusive, Zero, vec![]) {
+                Err(LockError::Conflict(c)) => c.relation,
+                other => panic!("expected a conflict, got {:?}", other),
+            }
+        };
+        assert_eq!(relation("/a/b"), ConflictRelation::Ancestor);
+        assert_eq!(relation("/a/"), ConflictRelation::Self_);
+    }
+
+    #[test]
+    fn lock_on_root() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.discover(&p("/")), vec![lock.clone()]);
+        assert!(ls.check(&p("/"), Some("y"), false, false, vec![]).is_err());
+        assert!(ls
+            .check(&p("/"), Some("x"), false, false, vec![&lock.token])
+            .is_ok());
+        // a depth-0 lock on the root does not cover its members.
+        assert!(ls.check(&p("/a"), Some("y"), false, false, vec![]).is_ok());
+        ls.unlock(&p("/"), Some("x"), &lock.token).unwrap();
+        assert!(ls.discover(&p("/")).is_empty());
+
+        let lock = ls
+            .lock(&p("/"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert_eq!(ls.discover(&p("/")), vec![lock.clone()]);
+        assert_eq!(ls.discover(&p("/a/b")), vec![lock]);
+        assert!(ls.check(&p("/"), Some("y"), false, false, vec![]).is_err());
+        assert!(ls.check(&p("/a/b"), Some("y"), false, false, vec![]).is_err());
+    }
+
+    #[test]
+    fn locks_at() {
+        let ls = MemLs::new();
+        let parent = ls
+            .lock(&p("/a/"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls.locks_at(&p("/a/b")).is_empty());
+        let tokens = vec![parent.token.as_str()];
+        let lock = ls
+            .lock(&p("/a/b"), None, None, None, Exclusive, Zero, tokens)
+            .unwrap();
+        assert_eq!(ls.locks_at(&p("/a/b")), vec![lock]);
+        assert_eq!(ls.locks_at(&p("/a/")), vec![parent]);
+        assert_eq!(ls.discover(&p("/a/b")).len(), 2);
+        assert!(ls.locks_at(&p("/x")).is_empty());
+    }
+
+    #[test]
+    fn locked_path_count() {
+        let ls = MemLs::new();
+        assert_eq!(ls.locked_path_count(), 0);
+        ls.lock(&p("/a/"), None, None, None, Shared, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/a/"), None, None, None, Shared, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b/c"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        let timeout = Some(Duration::new(0, 0));
+        ls.lock(&p("/d"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.locked_path_count(), 2);
+        assert_eq!(ls.stats().locks, 3);
+    }
+
+    #[test]
+    fn for_each_lock() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a/"), None, None, None, Shared, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        let timeout = Some(Duration::new(0, 0));
+        ls.lock(&p("/c"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        let mut paths = Vec::new();
+        ls.for_each_lock(|path, lock| {
+            assert_eq!(path, &lock.path);
+            paths.push(path.as_url_string());
+        });
+        paths.sort();
+        assert_eq!(paths, vec!["/a/", "/a/b"]);
+        assert_eq!(ls.dump().len(), 3);
+    }
+
+    #[test]
+    fn would_lock() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        assert!(ls.would_lock(&p("/a/b"), None, Shared, Zero, vec![]).is_ok());
+        assert_eq!(
+            ls.would_lock(&p("/a/b"), None, Exclusive, Zero, vec![]),
+            Err(lock.clone())
+        );
+        assert_eq!(
+            ls.would_lock(&p("/a/"), None, Exclusive, Infinity, vec![]),
+            Err(lock.clone())
+        );
+        assert!(ls.would_lock(&p("/a/"), None, Exclusive, Zero, vec![]).is_ok());
+        let tokens = vec![lock.token.as_str()];
+        assert!(ls.would_lock(&p("/a/b"), None, Exclusive, Zero, tokens).is_ok());
+        // nothing was created.
+        assert_eq!(ls.all_locks(), vec![lock]);
+        assert_eq!(ls.stats().nodes, 3);
+    }
+
+    #[test]
+    fn lock_or_conflict() {
+        let ls = MemLs::builder().max_locks_per_node(1).build();
+        let lock = ls
+            .lock_or_conflict(&p("/a/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap()
+            .unwrap();
+        let conflict = ls
+            .lock_or_conflict(&p("/a/"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(conflict.lock, lock);
+        assert_eq!(conflict.relation, ConflictRelation::Descendant);
+        let tokens = vec![lock.token.as_str()];
+        match ls.lock_or_conflict(&p("/a/b"), None, None, None, Exclusive, Zero, tokens) {
+            Err(LockError::TooManyLocks) => {},
+            other => panic!("expected TooManyLocks, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lock_with_extra() {
+        let ls = MemLs::new();
+        let mut extra = HashMap::new();
+        extra.insert("client".to_string(), "192.0.2.1".to_string());
+        let lock = ls
+            .lock_with_extra(&p("/a"), None, None, None, Exclusive, Zero, vec![], extra.clone())
+            .unwrap();
+        assert_eq!(lock.extra, extra);
+        assert_eq!(ls.discover(&p("/a"))[0].extra, extra);
+        let lock = ls.refresh(&p("/a"), None, &lock.token, None).unwrap();
+        assert_eq!(lock.extra, extra);
+        ls.relocate(&p("/a"), &p("/b")).unwrap();
+        assert_eq!(ls.discover(&p("/b"))[0].extra, extra);
+        assert_eq!(ls.dump()[0].extra, extra);
+        let lock = ls
+            .lock(&p("/c"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(lock.extra.is_empty());
+    }
+
+    #[test]
+    fn strict_tokens() {
+        let ls = MemLs::builder().strict_tokens(true).build();
+        let lock = ls
+            .lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let res = ls.check_tokens(&p("/a"), None, false, false, vec!["bogus"]);
+        assert_eq!(res, Err(CheckError::MalformedToken("bogus".to_string())));
+        let res = ls.check_tokens(&p("/a"), None, false, false, vec![&lock.token, "bogus"]);
+        assert!(matches!(res, Err(CheckError::MalformedToken(_))));
+        let other = LockToken::generate();
+        let res = ls.check_tokens(&p("/a"), None, false, false, vec![&other]);
+        assert_eq!(res, Err(CheckError::Locked(Box::new(lock.clone()))));
+        assert!(ls
+            .check_tokens(&p("/a"), None, false, false, vec![&lock.token])
+            .is_ok());
+
+        // lenient by default.
+        let ls = MemLs::new();
+        ls.lock(&p("/a"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let res = ls.check_tokens(&p("/a"), None, false, false, vec!["bogus"]);
+        assert!(matches!(res, Err(CheckError::Locked(_))));
+    }
+
+    #[test]
+    fn shared_lock_count() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a/"), None, None, None, Shared, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/a/b"), None, None, None, Shared, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.shared_lock_count(&p("/a/b")), 2);
+        assert_eq!(ls.shared_lock_count(&p("/a/")), 1);
+        assert_eq!(ls.shared_lock_count(&p("/x")), 0);
+    }
+
+    #[test]
+    fn sweeper() {
+        // one round of the sweeper at a time, with a clock we control.
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let timeout = Some(Duration::from_secs(10));
+        ls.lock(&p("/a"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        let weak = Arc::downgrade(&ls.0);
+        assert!(sweep_weak(&weak));
+        assert_eq!(ls.read().lock_count(), 1);
+        clock.advance(11);
+        assert!(sweep_weak(&weak));
+        assert_eq!(ls.read().lock_count(), 0);
+        assert!(ls.is_empty());
+        // the sweeper does not keep the locksystem alive, and stops
+        // once it is gone.
+        drop(ls);
+        assert!(weak.upgrade().is_none());
+        assert!(!sweep_weak(&weak));
+    }
+
+    #[test]
+    fn contention_stats() {
+        let ls = MemLs::new();
+        ls.discover(&p("/a"));
+        let stats = ls.contention_stats();
+        assert_eq!(stats.acquisitions, 1);
+        assert_eq!(stats.contended, 0);
+
+        let guard = ls.write();
+        let ls2 = ls.clone();
+        let handle = std::thread::spawn(move || ls2.discover(&p("/a")));
+        // the other thread is counted before it waits for the lock.
+        while ls.contention_stats().contended == 0 {
+            std::thread::yield_now();
+        }
+        drop(guard);
+        handle.join().unwrap();
+        let stats = ls.contention_stats();
+        assert_eq!(stats.acquisitions, 3);
+        assert_eq!(stats.contended, 1);
+    }
+
+    #[test]
+    fn discover_within() {
+        let ls = MemLs::new();
+        let mut tokens = Vec::new();
+        for path in &["/a/", "/a/b/", "/a/b/c"] {
+            let t = tokens.iter().map(String::as_str).collect();
+            let lock = ls
+                .lock(&p(path), None, None, None, Exclusive, Infinity, t)
+                .unwrap();
+            tokens.push(lock.token.to_string());
+        }
+        let paths = |locks: Vec<DavLock>| {
+            let mut v = locks
+                .into_iter()
+                .map(|l| l.path.as_url_string())
+                .collect::<Vec<_>>();
+            v.sort();
+            v
+        };
+        assert_eq!(paths(ls.discover_within(&p("/a/b/c"), 0)), vec!["/a/b/c"]);
+        assert_eq!(
+            paths(ls.discover_within(&p("/a/b/c"), 1)),
+            vec!["/a/b/", "/a/b/c"]
+        );
+        assert_eq!(ls.discover_within(&p("/a/b/c"), 2).len(), 3);
+        assert_eq!(ls.discover_within(&p("/a/b/c"), 100).len(), 3);
+        assert_eq!(ls.discover(&p("/a/b/c")).len(), 3);
+    }
+
+    #[test]
+    fn failed_lock_leaves_tree_unchanged() {
+        let ls = MemLs::builder()
+            .max_locks_per_node(1)
+            .max_path_depth(3)
+            .forbid_infinite_timeout(true)
+            .build();
+        let timeout = Some(Duration::from_secs(60));
+        let lock = ls
+            .lock(&p("/a/"), Some("x"), None, timeout, Exclusive, Infinity, vec![])
+            .unwrap();
+        let nodes = ls.read().tree.len();
+
+        // conflict with the deep lock on /a/.
+        let res = ls.lock(&p("/a/b/c"), Some("y"), None, timeout, Exclusive, Zero, vec![]);
+        assert!(matches!(res, Err(LockError::Conflict(_))));
+        // too many segments.
+        let res = ls.lock(&p("/b/c/d/e"), Some("y"), None, timeout, Exclusive, Zero, vec![]);
+        assert!(matches!(res, Err(LockError::PathTooDeep)));
+        // infinite timeout.
+        let res = ls.lock(&p("/b/c"), Some("y"), None, None, Exclusive, Zero, vec![]);
+        assert!(matches!(res, Err(LockError::InfiniteTimeout)));
+        // /a/ already holds one lock.
+        let tokens = vec![lock.token.as_str()];
+        let res = ls.lock(&p("/a/"), Some("x"), None, timeout, Exclusive, Zero, tokens);
+        assert!(matches!(res, Err(LockError::TooManyLocks)));
+        assert_eq!(ls.read().tree.len(), nodes);
+
+        // a successful lock does create the nodes.
+        ls.lock(&p("/b/c"), Some("y"), None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        assert_eq!(ls.read().tree.len(), nodes + 2);
+    }
+
+    #[test]
+    fn unlock_ignores_trailing_slash() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/b/"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls.unlock(&p("/a/b"), None, &lock.token).is_ok());
+        let lock = ls
+            .lock(&p("/a/c"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(ls.unlock(&p("/a/c/"), None, &lock.token).is_ok());
+        assert!(ls.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokio_sweeper() {
+        let clock = TestClock::new();
+        let ls = MemLs::with_clock(clock.clone());
+        let interval = Duration::from_secs(60);
+        let (join_handle, abort_handle) = ls.spawn_sweeper_tokio(interval);
+        let timeout = Some(Duration::from_secs(10));
+        ls.lock(&p("/a"), None, None, timeout, Exclusive, Zero, vec![])
+            .unwrap();
+        // let the task start, it then waits for the next tick.
+        tokio::task::yield_now().await;
+        clock.advance(11);
+        assert!(!ls.is_empty());
+        tokio::time::advance(interval).await;
+        tokio::task::yield_now().await;
+        assert!(ls.is_empty());
+        abort_handle.abort();
+        join_handle.await.unwrap();
+    }
+
+    #[test]
+    fn rekey() {
+        let ls = MemLs::new();
+        let lock = ls
+            .lock(&p("/a/"), Some("x"), None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        let other = ls
+            .lock(&p("/b"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let new_token = "opaquelocktoken:new";
+        assert!(ls.rekey(&p("/a/"), &lock.token, "not a token").is_err());
+        assert!(ls.rekey(&p("/a/"), &lock.token, &other.token).is_err());
+        assert!(ls.rekey(&p("/b"), &lock.token, new_token).is_err());
+
+        let rekeyed = ls.rekey(&p("/a/"), &lock.token, new_token).unwrap();
+        assert_eq!(rekeyed.token, new_token);
+        assert_eq!(rekeyed.path, lock.path);
+        assert_eq!(rekeyed.created_at, lock.created_at);
+        assert!(ls
+            .check(&p("/a/b"), Some("x"), false, false, vec![new_token])
+            .is_ok());
+        assert!(ls
+            .check(&p("/a/b"), Some("x"), false, false, vec![&lock.token])
+            .is_err());
+        assert!(ls.unlock(&p("/a/"), Some("x"), &lock.token).is_err());
+        assert!(ls.unlock(&p("/a/"), Some("x"), new_token).is_ok());
+    }
+
+    #[test]
+    fn max_owner_size() {
+        let ls = MemLs::builder().max_owner_size(64).build();
+        let mut owner = Element::new2("D:owner");
+        owner.push_element(Element::new2("D:href").text("mailto:a@example.com"));
+        assert!(ls
+            .lock(&p("/a"), None, Some(&owner), None, Exclusive, Zero, vec![])
+            .is_ok());
+        let mut owner = Element::new2("D:owner");
+        owner.push_element(Element::new2("D:href").text("x".repeat(100)));
+        match ls.lock(&p("/b"), None, Some(&owner), None, Exclusive, Zero, vec![]) {
+            Err(LockError::OwnerTooLarge) => {},
+            other => panic!("expected OwnerTooLarge, got {:?}", other),
+        }
+        assert!(ls
+            .lock(&p("/b"), None, None, None, Exclusive, Zero, vec![])
+            .is_ok());
+    }
+
+    #[test]
+    fn lock_conditional() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a/"), None, None, None, Shared, Infinity, vec![])
+            .unwrap();
+        // a shared lock is compatible with another shared lock ..
+        assert!(ls
+            .lock_conditional(&p("/a/b"), None, None, None, Shared, Zero, vec![], false)
+            .is_ok());
+        // .. unless the path must be pristine.
+        match ls.lock_conditional(&p("/a/c"), None, None, None, Shared, Zero, vec![], true) {
+            Err(LockError::Conflict(c)) => assert_eq!(c.relation, ConflictRelation::Ancestor),
+            other => panic!("expected Conflict, got {:?}", other),
+        }
+        assert!(ls
+            .lock_conditional(&p("/d"), None, None, None, Shared, Infinity, vec![], true)
+            .is_ok());
+    }
+
+    #[test]
+    fn clear() {
+        let ls = MemLs::new();
+        ls.lock(&p("/a/b"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        ls.lock(&p("/c"), None, None, None, Shared, Zero, vec![]).unwrap();
+        assert!(ls.check(&p("/a/b/c"), None, false, false, vec![]).is_err());
+        assert_eq!(ls.clear(), 2);
+        assert!(ls.check(&p("/a/b/c"), None, false, false, vec![]).is_ok());
+        assert!(ls.all_locks().is_empty());
+        assert_eq!(ls.stats().nodes, 1);
+        assert!(ls
+            .lock(&p("/c"), None, None, None, Exclusive, Zero, vec![])
+            .is_ok());
+    }
+
+    #[test]
+    fn control_characters_in_path() {
+        let ls = MemLs::new();
+        let mut path = p("/a/");
+        path.push_segment(b"b\0c");
+        match ls.lock(&path, None, None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::InvalidPath) => {},
+            other => panic!("expected InvalidPath, got {:?}", other),
+        }
+        let res = ls.lock(&p("/a/b%1fc"), None, None, None, Exclusive, Zero, vec![]);
+        assert!(matches!(res, Err(LockError::InvalidPath)));
+        assert_eq!(ls.stats().nodes, 1);
+        assert!(ls
+            .lock(&p("/a/b%7fc"), None, None, None, Exclusive, Zero, vec![])
+            .is_ok());
+    }
+
+    #[test]
+    fn on_move() {
+        use std::sync::Mutex;
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let r = released.clone();
+        let ls = MemLs::builder()
+            .on_event(move |ev| {
+                if let LockEvent::Released { path, .. } = ev {
+                    r.lock().unwrap().push(path.as_url_string());
+                }
+            })
+            .build();
+        let held = ls
+            .lock(&p("/a/b"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/a/c"), None, None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/x/"), None, None, None, Exclusive, Infinity, vec![])
+            .unwrap();
+        assert!(ls
+            .on_move(&p("/a/"), &p("/a/d/"), vec![held.token.as_str()])
+            .is_err());
+        // to an ancestor: fails, and no locks are removed.
+        assert_eq!(
+            ls.on_move(&p("/a/b"), &p("/a/"), vec![]),
+            Err(FsError::Forbidden)
+        );
+        assert!(released.lock().unwrap().is_empty());
+        assert_eq!(ls.all_locks().len(), 3);
+        assert_eq!(ls.locks_at(&p("/a/b")), vec![held.clone()]);
+
+        ls.on_move(&p("/a/"), &p("/x/y/"), vec![held.token.as_str()])
+            .unwrap();
+        assert_eq!(*released.lock().unwrap(), vec!["/a/c".to_string()]);
+        assert!(ls.discover(&p("/a/b")).is_empty());
+        let locks = ls.locks_at(&p("/x/y/b"));
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].token, held.token);
+        assert_eq!(locks[0].path.as_url_string(), "/x/y/b");
+        assert!(ls.locks_at(&p("/x/y/c")).is_empty());
+        assert_eq!(ls.all_locks().len(), 2);
+
+        // nothing held: all locks are removed.
+        ls.on_move(&p("/x/y/"), &p("/z/"), vec![]).unwrap();
+        assert!(ls.locks_at(&p("/z/b")).is_empty());
+        assert!(ls.locks_at(&p("/x/y/b")).is_empty());
+        assert_eq!(ls.all_locks().len(), 1);
+    }
+
+    #[test]
+    fn on_move_replaces_destination() {
+        use std::sync::Mutex;
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let r = released.clone();
+        let ls = MemLs::builder()
+            .on_event(move |ev| {
+                if let LockEvent::Released { path, .. } = ev {
+                    r.lock().unwrap().push(path.as_url_string());
+                }
+            })
+            .build();
+        let held = ls
+            .lock(&p("/a"), Some("u1"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/x"), Some("u2"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/y/z"), Some("u2"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+
+        // a lock moves along: the one at the destination goes.
+        ls.on_move(&p("/a"), &p("/x"), vec![held.token.as_str()])
+            .unwrap();
+        assert_eq!(*released.lock().unwrap(), vec!["/x".to_string()]);
+        let locks = ls.locks_at(&p("/x"));
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].token, held.token);
+
+        // nothing moves along: the destination goes all the same.
+        released.lock().unwrap().clear();
+        ls.on_move(&p("/b"), &p("/y"), vec![]).unwrap();
+        assert_eq!(*released.lock().unwrap(), vec!["/y/z".to_string()]);
+        assert!(ls.discover(&p("/y/z")).is_empty());
+        assert_eq!(ls.all_locks().len(), 1);
+
+        // the same for relocate, without events.
+        ls.lock(&p("/c"), Some("u2"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.relocate(&p("/d"), &p("/c")).unwrap();
+        assert!(ls.discover(&p("/c")).is_empty());
+        assert_eq!(ls.all_locks().len(), 1);
     }
-    tree.get_child(node_id, seg)
 }
@@ -0,0 +1,336 @@
+//! Locksystem adapter that limits the number of locks per principal.
+//!
+//! `QuotaLs` wraps another locksystem. All calls are passed on to it,
+//! but a `lock` by a principal that already holds the maximum number of
+//! locks fails with `LockError::QuotaExceeded` (507).
+//!
+//! Locks can go away without `QuotaLs` seeing an `unlock`: they expire,
+//! or they are removed by `delete` after a MOVE or DELETE. So instead of
+//! relying on events from the inner locksystem, `QuotaLs` remembers the
+//! token and path of every lock it handed out, and before it checks the
+//! quota of a principal it asks the inner locksystem (with `has_token`)
+//! which of those locks still exist. That works with any locksystem, and
+//! with `MemLs` it also finds locks that were moved by `relocate`. Now and
+//! then the locks of all principals are checked, so that principals that
+//! never lock again are forgotten as well.
+//!
+//! Locks without a principal are not limited.
+//!
+//! Adapters like this one can be stacked, since a `QuotaLs` is a
+//! `DavLockSystem` itself:
+//!
+//! ```
+//! use webdav_handler::{memls::MemLs, quotals::QuotaLs, DavHandler};
+//!
+//! let ls = QuotaLs::new(*MemLs::new(), 10);
+//! let dav_server = DavHandler::builder()
+//!     .locksystem(Box::new(ls))
+//!     .build_handler();
+//! ```
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use xmltree::Element;
+
+use crate::davpath::DavPath;
+use crate::ls::*;
+
+// The locks that were handed out.
+#[derive(Debug, Default)]
+struct Held {
+    // principal -> (token, path) of the locks that it holds.
+    locks:    HashMap<String, Vec<(LockToken, DavPath)>>,
+    // number of locks in "locks".
+    count:    usize,
+    // check all principals once "count" reaches this.
+    prune_at: usize,
+}
+
+// Do not bother checking all principals below this many locks.
+const MIN_PRUNE_AT: usize = 64;
+
+/// Locksystem adapter that limits the number of locks per principal.
+///
+/// Clones share the same counters.
+#[derive(Debug, Clone)]
+pub struct QuotaLs<L> {
+    inner: L,
+    quota: usize,
+    held:  Arc<Mutex<Held>>,
+}
+
+impl<L: DavLockSystem + Clone + 'static> QuotaLs<L> {
+    /// Wrap `inner`, allowing every principal at most `quota` locks.
+    pub fn new(inner: L, quota: usize) -> QuotaLs<L> {
+        QuotaLs {
+            inner,
+            quota,
+            held: Arc::new(Mutex::new(Held::default())),
+        }
+    }
+
+    /// The wrapped locksystem.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// The number of locks that `principal` holds.
+    pub fn count(&self, principal: &str) -> usize {
+        let mut held = self.held();
+        self.forget_stale(&mut held, principal);
+        held.locks.get(principal).map_or(0, |v| v.len())
+    }
+
+    // The counters never stay locked when a thread panics in the
+    // middle of an update, so a poisoned mutex can be used as is.
+    fn held(&self) -> MutexGuard<'_, Held> {
+        self.held.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Forget the locks of this principal that the inner locksystem
+    // does not know about anymore (unlocked, expired or deleted).
+    fn forget_stale(&self, held: &mut Held, principal: &str) {
+        if let Some(locks) = held.locks.get_mut(principal) {
+            let before = locks.len();
+            locks.retain(|(token, path)| self.inner.has_token(token, path));
+            held.count -= before - locks.len();
+            if locks.is_empty() {
+                held.locks.remove(principal);
+            }
+        }
+    }
+
+    // Forget the stale locks of all principals, if enough locks were
+    // handed out since the last time. That keeps the work per lock
+    // constant, and what is remembered at most about twice what exists.
+    fn forget_all_stale(&self, held: &mut Held) {
+        if held.count < held.prune_at {
+            return;
+        }
+        let inner = &self.inner;
+        for locks in held.locks.values_mut() {
+            locks.retain(|(token, path)| inner.has_token(token, path));
+        }
+        held.locks.retain(|_, locks| !locks.is_empty());
+        held.count = held.locks.values().map(|v| v.len()).sum();
+        held.prune_at = std::cmp::max(2 * held.count, MIN_PRUNE_AT);
+    }
+}
+
+impl<L: DavLockSystem + Clone + 'static> DavLockSystem for QuotaLs<L> {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        scope: LockScope,
+        depth: LockDepth,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<DavLock, LockError>
+    {
+        let principal = match principal {
+            Some(p) => p,
+            None => {
+                return self
+                    .inner
+                    .lock(path, None, owner, timeout, scope, depth, submitted_tokens)
+            },
+        };
+        // keep the counters locked, so that concurrent requests
+        // of the same principal cannot both get the last lock.
+        let mut held = self.held();
+        self.forget_all_stale(&mut held);
+        self.forget_stale(&mut held, principal);
+        let count = held.locks.get(principal).map_or(0, |v| v.len());
+        if count >= self.quota {
+            debug!("lock: {} already holds {} locks", principal, count);
+            return Err(LockError::QuotaExceeded);
+        }
+        let lock = self.inner.lock(
+            path,
+            Some(principal),
+            owner,
+            timeout,
+            scope,
+            depth,
+            submitted_tokens,
+        )?;
+        held.locks
+            .entry(principal.to_string())
+            .or_default()
+            .push((lock.token.clone(), lock.path.clone()));
+        held.count += 1;
+        Ok(lock)
+    }
+
+    fn unlock(&self, path: &DavPath, principal: Option<&str>, token: &str) -> Result<(), UnlockError> {
+        self.inner.unlock(path, principal, token)?;
+        let mut held = self.held();
+        let mut removed = 0;
+        for locks in held.locks.values_mut() {
+            let before = locks.len();
+            locks.retain(|(t, _)| t != token);
+            removed += before - locks.len();
+        }
+        held.count -= removed;
+        held.locks.retain(|_, locks| !locks.is_empty());
+        Ok(())
+    }
+
+    fn refresh(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, RefreshError>
+    {
+        self.inner.refresh(path, principal, token, timeout)
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        self.inner
+            .check(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn check_all(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), Vec<DavLock>>
+    {
+        self.inner
+            .check_all(path, principal, ignore_principal, deep, submitted_tokens)
+    }
+
+    fn check_many(
+        &self,
+        paths: &[(&DavPath, bool)],
+        principal: Option<&str>,
+        ignore_principal: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock>
+    {
+        self.inner
+            .check_many(paths, principal, ignore_principal, submitted_tokens)
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        self.inner.discover(path)
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        self.inner.delete(path)
+    }
+
+    fn has_token(&self, token: &str, path: &DavPath) -> bool {
+        self.inner.has_token(token, path)
+    }
+
+    fn supported_lock(&self) -> Vec<SupportedLockEntry> {
+        self.inner.supported_lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memls::MemLs;
+    use LockDepth::Zero;
+    use LockScope::Exclusive;
+
+    fn p(s: &str) -> DavPath {
+        DavPath::new(s).unwrap()
+    }
+
+    #[test]
+    fn quota() {
+        let ls = QuotaLs::new(*MemLs::new(), 2);
+        let l1 = ls
+            .lock(&p("/a"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        ls.lock(&p("/b"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        match ls.lock(&p("/c"), Some("x"), None, None, Exclusive, Zero, vec![]) {
+            Err(LockError::QuotaExceeded) => {},
+            other => panic!("expected QuotaExceeded, got {:?}", other),
+        }
+        // other principals, and anonymous locks, are not affected.
+        assert!(ls
+            .lock(&p("/c"), Some("y"), None, None, Exclusive, Zero, vec![])
+            .is_ok());
+        assert!(ls
+            .lock(&p("/d"), None, None, None, Exclusive, Zero, vec![])
+            .is_ok());
+
+        ls.unlock(&p("/a"), Some("x"), &l1.token).unwrap();
+        assert_eq!(ls.count("x"), 1);
+        assert!(ls
+            .lock(&p("/e"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .is_ok());
+        assert!(ls
+            .lock(&p("/f"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn quota_after_delete() {
+        let ls = QuotaLs::new(*MemLs::new(), 1);
+        ls.lock(&p("/a/b"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        assert!(ls
+            .lock(&p("/c"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .is_err());
+        // the lock goes away without an unlock.
+        ls.delete(&p("/a/")).unwrap();
+        assert_eq!(ls.count("x"), 0);
+        assert!(ls
+            .lock(&p("/c"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .is_ok());
+    }
+
+    #[test]
+    fn quota_after_relocate() {
+        let ls = QuotaLs::new(*MemLs::new(), 1);
+        ls.lock(&p("/a"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        // the lock keeps its token, but is somewhere else now.
+        ls.inner().relocate(&p("/a"), &p("/b")).unwrap();
+        assert_eq!(ls.count("x"), 1);
+        assert!(ls
+            .lock(&p("/c"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn forget_other_principals() {
+        let ls = QuotaLs::new(*MemLs::new(), 1);
+        for i in 0..MIN_PRUNE_AT {
+            let principal = format!("p{}", i);
+            let path = p(&format!("/{}", i));
+            ls.lock(&path, Some(&principal), None, None, Exclusive, Zero, vec![])
+                .unwrap();
+        }
+        // all those locks go away, the principals never lock again.
+        ls.delete(&p("/")).unwrap();
+        assert_eq!(ls.held().locks.len(), MIN_PRUNE_AT);
+        ls.lock(&p("/a"), Some("x"), None, None, Exclusive, Zero, vec![])
+            .unwrap();
+        let held = ls.held();
+        assert_eq!(held.locks.len(), 1);
+        assert_eq!(held.count, 1);
+    }
+}
@@ -4,7 +4,31 @@ use std::fmt::Debug;
 use std::hash::Hash;
 
 use crate::FsError;
-use crate::FsResult;
+
+/// Errors returned by the tree operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeError {
+    /// The node does not exist.
+    NotFound,
+    /// A node with this key already exists.
+    Exists,
+    /// Not allowed, e.g. deleting the root, deleting a node that still
+    /// has children, or moving a node below itself.
+    Forbidden,
+}
+
+/// Result type of the tree operations.
+pub type TreeResult<T> = Result<T, TreeError>;
+
+impl From<TreeError> for FsError {
+    fn from(e: TreeError) -> Self {
+        match e {
+            TreeError::NotFound => FsError::NotFound,
+            TreeError::Exists => FsError::Exists,
+            TreeError::Forbidden => FsError::Forbidden,
+        }
+    }
+}
 
 #[derive(Debug)]
 /// A tree contains a bunch of nodes.
@@ -29,6 +53,13 @@ pub struct Node<K: Eq + Hash, D> {
 // Iterator over the children of a node.
 pub struct Children<K>(std::vec::IntoIter<(K, u64)>);
 
+#[derive(Debug)]
+// Depth-first iterator over all nodes of a tree.
+pub struct Iter<'a, K: Eq + Hash, D> {
+    tree:  &'a Tree<K, D>,
+    stack: Vec<(u64, Vec<K>)>,
+}
+
 impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
     /// Get new tree and initialize the root with 'data'.
     pub fn new(data: D) -> Tree<K, D> {
@@ -54,11 +85,11 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
     }
 
     /// add a child node to an existing node.
-    pub fn add_child(&mut self, parent: u64, key: K, data: D, overwrite: bool) -> FsResult<u64> {
+    pub fn add_child(&mut self, parent: u64, key: K, data: D, overwrite: bool) -> TreeResult<u64> {
         {
-            let pnode = self.nodes.get(&parent).ok_or(FsError::NotFound)?;
+            let pnode = self.nodes.get(&parent).ok_or(TreeError::NotFound)?;
             if !overwrite && pnode.children.contains_key(&key) {
-                return Err(FsError::Exists);
+                return Err(TreeError::Exists);
             }
         }
         let id = self.new_node(parent, data);
@@ -70,13 +101,13 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
 
     /*
      * unused ...
-    pub fn remove_child(&mut self, parent: u64, key: &K) -> FsResult<()> {
+    pub fn remove_child(&mut self, parent: u64, key: &K) -> TreeResult<()> {
         let id = {
-            let pnode = self.nodes.get(&parent).ok_or(FsError::NotFound)?;
-            let id = *pnode.children.get(key).ok_or(FsError::NotFound)?;
+            let pnode = self.nodes.get(&parent).ok_or(TreeError::NotFound)?;
+            let id = *pnode.children.get(key).ok_or(TreeError::NotFound)?;
             let node = self.nodes.get(&id).unwrap();
             if node.children.len() > 0 {
-                return Err(FsError::Forbidden);
+                return Err(TreeError::Forbidden);
             }
             id
         };
@@ -89,19 +120,19 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
     }*/
 
     /// Get a child node by key K.
-    pub fn get_child<Q: ?Sized>(&self, parent: u64, key: &Q) -> FsResult<u64>
+    pub fn get_child<Q>(&self, parent: u64, key: &Q) -> TreeResult<u64>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
-        let pnode = self.nodes.get(&parent).ok_or(FsError::NotFound)?;
-        let id = pnode.children.get(key).ok_or(FsError::NotFound)?;
+        let pnode = self.nodes.get(&parent).ok_or(TreeError::NotFound)?;
+        let id = pnode.children.get(key).ok_or(TreeError::NotFound)?;
         Ok(*id)
     }
 
     /// Get all children of this node. Returns an iterator over <K, D>.
-    pub fn get_children(&self, parent: u64) -> FsResult<Children<K>> {
-        let pnode = self.nodes.get(&parent).ok_or(FsError::NotFound)?;
+    pub fn get_children(&self, parent: u64) -> TreeResult<Children<K>> {
+        let pnode = self.nodes.get(&parent).ok_or(TreeError::NotFound)?;
         let mut v = Vec::new();
         for (k, i) in &pnode.children {
             v.push(((*k).clone(), *i));
@@ -109,20 +140,73 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
         Ok(Children(v.into_iter()))
     }
 
+    /// Iterate over all nodes in the tree, depth-first, starting at the root.
+    /// Returns the path of keys from the root to the node, and the node's data.
+    /// The path of the root node is empty.
+    pub fn iter(&self) -> Iter<'_, K, D> {
+        Iter {
+            tree:  self,
+            stack: vec![(ROOT_ID, Vec::new())],
+        }
+    }
+
+    /// Like `iter`, but only returns the nodes for which `pred` returns true.
+    pub fn find<'a, F>(&'a self, pred: F) -> impl Iterator<Item = (Vec<K>, &'a D)> + 'a
+    where F: Fn(&D) -> bool + 'a {
+        self.iter().filter(move |(_, data)| pred(data))
+    }
+
+    /// Remove all nodes, and initialize the root with 'data' again.
+    /// Node ids start over as well.
+    pub fn clear(&mut self, data: D) {
+        *self = Tree::new(data);
+    }
+
+    /// Number of nodes in the tree, including the root.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// A tree is empty if it only has a root node.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    /// Get the id of the parent of a node. The root has no parent.
+    pub fn get_parent(&self, id: u64) -> TreeResult<u64> {
+        if id == ROOT_ID {
+            return Err(TreeError::NotFound);
+        }
+        let n = self.nodes.get(&id).ok_or(TreeError::NotFound)?;
+        Ok(n.parent_id)
+    }
+
+    /// Get the depth of a node: the number of steps up to the root.
+    /// The root itself has depth 0. `None` if there is no such node.
+    pub fn depth(&self, id: u64) -> Option<usize> {
+        let mut depth = 0;
+        let mut id = id;
+        while id != ROOT_ID {
+            id = self.nodes.get(&id)?.parent_id;
+            depth += 1;
+        }
+        Some(depth)
+    }
+
     /// Get reference to a node.
-    pub fn get_node(&self, id: u64) -> FsResult<&D> {
-        let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
+    pub fn get_node(&self, id: u64) -> TreeResult<&D> {
+        let n = self.nodes.get(&id).ok_or(TreeError::NotFound)?;
         Ok(&n.data)
     }
 
     /// Get mutable reference to a node.
-    pub fn get_node_mut(&mut self, id: u64) -> FsResult<&mut D> {
-        let n = self.nodes.get_mut(&id).ok_or(FsError::NotFound)?;
+    pub fn get_node_mut(&mut self, id: u64) -> TreeResult<&mut D> {
+        let n = self.nodes.get_mut(&id).ok_or(TreeError::NotFound)?;
         Ok(&mut n.data)
     }
 
-    fn delete_node_from_parent(&mut self, id: u64) -> FsResult<()> {
-        let parent_id = self.nodes.get(&id).ok_or(FsError::NotFound)?.parent_id;
+    fn delete_node_from_parent(&mut self, id: u64) -> TreeResult<()> {
+        let parent_id = self.nodes.get(&id).ok_or(TreeError::NotFound)?.parent_id;
         let key = {
             let pnode = self.nodes.get(&parent_id).unwrap();
             let mut key = None;
@@ -141,39 +225,44 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
     }
 
     /// Delete a node. Fails if node has children. Returns node itself.
-    pub fn delete_node(&mut self, id: u64) -> FsResult<Node<K, D>> {
+    pub fn delete_node(&mut self, id: u64) -> TreeResult<Node<K, D>> {
         {
-            let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
+            let n = self.nodes.get(&id).ok_or(TreeError::NotFound)?;
             if n.children.len() > 0 {
-                return Err(FsError::Forbidden);
+                return Err(TreeError::Forbidden);
             }
         }
         self.delete_node_from_parent(id)?;
         Ok(self.nodes.remove(&id).unwrap())
     }
 
-    /// Delete a subtree.
-    pub fn delete_subtree(&mut self, id: u64) -> FsResult<()> {
+    /// Delete a subtree. The root node cannot be deleted.
+    pub fn delete_subtree(&mut self, id: u64) -> TreeResult<()> {
+        if id == ROOT_ID {
+            return Err(TreeError::Forbidden);
+        }
         let children = {
-            let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
+            let n = self.nodes.get(&id).ok_or(TreeError::NotFound)?;
             n.children.iter().map(|(_, &v)| v).collect::<Vec<u64>>()
         };
         for c in children.into_iter() {
             self.delete_subtree(c)?;
         }
-        self.delete_node_from_parent(id)
+        self.delete_node_from_parent(id)?;
+        self.nodes.remove(&id);
+        Ok(())
     }
 
     /// Move a node to a new position and new name in the tree.
     /// If "overwrite" is true, will replace an existing
     /// node, but only if it doesn't have any children.
-    pub fn move_node(&mut self, id: u64, new_parent: u64, new_name: K, overwrite: bool) -> FsResult<()> {
+    pub fn move_node(&mut self, id: u64, new_parent: u64, new_name: K, overwrite: bool) -> TreeResult<()> {
         let dest = {
-            let pnode = self.nodes.get(&new_parent).ok_or(FsError::NotFound)?;
+            let pnode = self.nodes.get(&new_parent).ok_or(TreeError::NotFound)?;
             if let Some(cid) = pnode.children.get(&new_name) {
                 let cnode = self.nodes.get(cid).unwrap();
                 if !overwrite || cnode.children.len() > 0 {
-                    return Err(FsError::Exists);
+                    return Err(TreeError::Exists);
                 }
                 Some(*cid)
             } else {
@@ -189,6 +278,75 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
         pnode.children.insert(new_name, id);
         Ok(())
     }
+
+    /// Move a node and everything below it to a new parent, under a new name.
+    /// Fails if the new name already exists, or if the new parent is
+    /// the node itself or one of its descendants.
+    pub fn move_subtree(&mut self, id: u64, new_parent: u64, new_name: K) -> TreeResult<()> {
+        if id == ROOT_ID {
+            return Err(TreeError::Forbidden);
+        }
+        let mut p = new_parent;
+        while p != ROOT_ID {
+            if p == id {
+                return Err(TreeError::Forbidden);
+            }
+            p = self.nodes.get(&p).ok_or(TreeError::NotFound)?.parent_id;
+        }
+        self.move_node(id, new_parent, new_name, false)
+    }
+
+    /// Copy a node and everything below it to a new parent, under a new name.
+    /// The data of every node is copied with `clone_data`, so the caller
+    /// decides how it is transformed. Returns the id of the new node.
+    /// Fails like `move_subtree`.
+    pub fn copy_subtree<F>(
+        &mut self,
+        id: u64,
+        new_parent: u64,
+        new_name: K,
+        clone_data: F,
+    ) -> TreeResult<u64>
+    where
+        F: Fn(&D) -> D,
+    {
+        if id == ROOT_ID {
+            return Err(TreeError::Forbidden);
+        }
+        let mut p = new_parent;
+        while p != ROOT_ID {
+            if p == id {
+                return Err(TreeError::Forbidden);
+            }
+            p = self.nodes.get(&p).ok_or(TreeError::NotFound)?.parent_id;
+        }
+        let data = clone_data(&self.nodes.get(&id).ok_or(TreeError::NotFound)?.data);
+        let new_id = self.add_child(new_parent, new_name, data, false)?;
+
+        let mut stack = vec![(id, new_id)];
+        while let Some((src, dst)) = stack.pop() {
+            let children = self.nodes[&src]
+                .children
+                .iter()
+                .map(|(k, &v)| (k.clone(), v))
+                .collect::<Vec<_>>();
+            for (key, child_id) in children {
+                let data = clone_data(&self.nodes[&child_id].data);
+                let new_child_id = self.add_child(dst, key, data, false)?;
+                stack.push((child_id, new_child_id));
+            }
+        }
+        Ok(new_id)
+    }
+}
+
+impl<K: Eq + Hash + Debug + Clone + Ord, D: Debug> Tree<K, D> {
+    /// Get all children of this node, sorted by key.
+    pub fn get_children_sorted(&self, parent: u64) -> TreeResult<Vec<(K, u64)>> {
+        let mut v: Vec<_> = self.get_children(parent)?.collect();
+        v.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(v)
+    }
 }
 
 impl<K> Iterator for Children<K> {
@@ -197,3 +355,135 @@ impl<K> Iterator for Children<K> {
         self.0.next()
     }
 }
+
+impl<'a, K: Eq + Hash + Clone, D> Iterator for Iter<'a, K, D> {
+    type Item = (Vec<K>, &'a D);
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, path) = self.stack.pop()?;
+        let node = self.tree.nodes.get(&id)?;
+        for (k, &child_id) in &node.children {
+            let mut child_path = path.clone();
+            child_path.push(k.clone());
+            self.stack.push((child_id, child_path));
+        }
+        Some((path, &node.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter() {
+        let mut t = Tree::new(0);
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        t.add_child(a, "b", 2, false).unwrap();
+        t.add_child(ROOT_ID, "c", 3, false).unwrap();
+        let mut v: Vec<_> = t.iter().map(|(p, &d)| (p.join("/"), d)).collect();
+        assert_eq!(v[0], (String::new(), 0));
+        v.sort();
+        assert_eq!(
+            v,
+            vec![
+                (String::new(), 0),
+                ("a".to_string(), 1),
+                ("a/b".to_string(), 2),
+                ("c".to_string(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_children_sorted() {
+        let mut t = Tree::new(0);
+        for (i, k) in ["d", "b", "a", "c"].iter().enumerate() {
+            t.add_child(ROOT_ID, *k, i, false).unwrap();
+        }
+        let keys: Vec<_> = t
+            .get_children_sorted(ROOT_ID)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, vec!["a", "b", "c", "d"]);
+        assert!(t.get_children_sorted(1000).is_err());
+    }
+
+    #[test]
+    fn find() {
+        let mut t = Tree::new(0);
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        t.add_child(a, "b", 2, false).unwrap();
+        t.add_child(ROOT_ID, "c", 3, false).unwrap();
+        let mut v: Vec<_> = t.find(|d| d % 2 == 1).map(|(p, &d)| (p.join("/"), d)).collect();
+        v.sort();
+        assert_eq!(v, vec![("a".to_string(), 1), ("c".to_string(), 3)]);
+        assert_eq!(t.find(|d| *d > 3).count(), 0);
+    }
+
+    #[test]
+    fn move_subtree() {
+        let mut t = Tree::new(0);
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        let b = t.add_child(a, "b", 2, false).unwrap();
+        let c = t.add_child(ROOT_ID, "c", 3, false).unwrap();
+        assert!(t.move_subtree(a, b, "x").is_err());
+        assert!(t.move_subtree(a, c, "x").is_ok());
+        let x = t.get_child(c, "x").unwrap();
+        assert_eq!(t.get_child(x, "b").unwrap(), b);
+        assert!(t.get_child(ROOT_ID, "a").is_err());
+    }
+
+    #[test]
+    fn copy_subtree() {
+        let mut t = Tree::new(0);
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        let b = t.add_child(a, "b", 2, false).unwrap();
+        let c = t.add_child(ROOT_ID, "c", 3, false).unwrap();
+        assert!(t.copy_subtree(a, b, "x", |d| *d).is_err());
+        assert!(t.copy_subtree(a, ROOT_ID, "c", |d| *d).is_err());
+        let x = t.copy_subtree(a, c, "x", |d| d * 10).unwrap();
+        assert_eq!(*t.get_node(x).unwrap(), 10);
+        let xb = t.get_child(x, "b").unwrap();
+        assert_ne!(xb, b);
+        assert_eq!(*t.get_node(xb).unwrap(), 20);
+        assert_eq!(*t.get_node(b).unwrap(), 2);
+        assert_eq!(t.len(), 6);
+    }
+
+    #[test]
+    fn len() {
+        let mut t = Tree::new(0);
+        assert!(t.is_empty());
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        t.add_child(a, "b", 2, false).unwrap();
+        assert_eq!(t.len(), 3);
+        t.delete_subtree(a).unwrap();
+        assert!(t.is_empty());
+        assert!(t.delete_subtree(ROOT_ID).is_err());
+    }
+
+    #[test]
+    fn clear() {
+        let mut t = Tree::new(0);
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        t.add_child(a, "b", 2, false).unwrap();
+        t.clear(5);
+        assert!(t.is_empty());
+        assert_eq!(t.get_node(ROOT_ID).unwrap(), &5);
+        assert!(t.get_child(ROOT_ID, "a").is_err());
+    }
+
+    #[test]
+    fn depth() {
+        let mut t = Tree::new(0);
+        let a = t.add_child(ROOT_ID, "a", 1, false).unwrap();
+        let b = t.add_child(a, "b", 2, false).unwrap();
+        assert_eq!(t.depth(ROOT_ID), Some(0));
+        assert_eq!(t.depth(a), Some(1));
+        assert_eq!(t.depth(b), Some(2));
+        t.delete_node(b).unwrap();
+        assert_eq!(t.depth(b), None);
+    }
+}